@@ -0,0 +1,54 @@
+extern crate calculator;
+#[macro_use]
+extern crate criterion;
+
+use calculator::parser::{self, AngleMode, Environment, VariableValue};
+use criterion::{black_box, Criterion};
+
+const EXPR: &str = "3x^2 + 2sin(x)cos(x) - sqrt(abs(x) + 1) / (x^2 + 1)";
+const POINTS: usize = 1000;
+
+fn points() -> impl Iterator<Item = f64> {
+    (0..POINTS).map(|i| i as f64 / POINTS as f64 * 10.0 - 5.0)
+}
+
+fn eval_math(c: &mut Criterion) {
+    let mut env = Environment::new();
+    c.bench_function("eval_math", |b| {
+        b.iter(|| {
+            for x in points() {
+                env.set("x".to_string(), VariableValue::Constant(x));
+                black_box(parser::eval_math(EXPR, AngleMode::Rad, &mut env).unwrap());
+            }
+        })
+    });
+}
+
+fn compiled(c: &mut Criterion) {
+    let env = Environment::new();
+    let compiled = parser::compile(EXPR, &["x".to_string()], AngleMode::Rad, &env).unwrap();
+    c.bench_function("compiled", |b| {
+        b.iter(|| {
+            for x in points() {
+                black_box(compiled.eval(&[x]).unwrap());
+            }
+        })
+    });
+}
+
+fn columns(c: &mut Criterion) {
+    let env = Environment::new();
+    let compiled = parser::compile(EXPR, &["x".to_string()], AngleMode::Rad, &env).unwrap();
+    let xs: Vec<f64> = points().collect();
+    c.bench_function("columns", |b| {
+        b.iter(|| black_box(compiled.eval_columns(&[&xs])))
+    });
+
+    let xs: Vec<f64> = (0..POINTS * 100).map(|i| i as f64 / 1E4 - 5.0).collect();
+    c.bench_function("columns_parallel", |b| {
+        b.iter(|| black_box(compiled.eval_columns_parallel(&[&xs], 4)))
+    });
+}
+
+criterion_group!(benches, eval_math, compiled, columns);
+criterion_main!(benches);
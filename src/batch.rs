@@ -0,0 +1,213 @@
+use parser::{self, AngleMode, CalcError, Environment, VariableValue};
+use serde_json::{Map, Value as Json};
+use std::io::{self, BufRead, Write};
+
+struct Request {
+    id: Option<Json>,
+    expr: String,
+    mode: AngleMode,
+    vars: Vec<(String, f64)>,
+}
+
+enum BatchError {
+    Input(String),
+    Calc(CalcError),
+}
+
+fn parse_mode(mode: &Json) -> Result<AngleMode, BatchError> {
+    match mode.as_str() {
+        Some("deg") => Ok(AngleMode::Deg),
+        Some("rad") => Ok(AngleMode::Rad),
+        Some("grad") => Ok(AngleMode::Grad),
+        _ => Err(BatchError::Input(format!(
+            "\"mode\" must be \"deg\", \"rad\" or \"grad\", found {}",
+            mode
+        ))),
+    }
+}
+
+fn parse_vars(vars: &Json) -> Result<Vec<(String, f64)>, BatchError> {
+    let vars = vars
+        .as_object()
+        .ok_or_else(|| BatchError::Input("\"vars\" must be an object".to_string()))?;
+    vars.iter()
+        .map(|(name, value)| {
+            value
+                .as_f64()
+                .map(|value| (name.clone(), value))
+                .ok_or_else(|| BatchError::Input(format!("Variable \"{}\" must be a number", name)))
+        })
+        .collect()
+}
+
+fn parse_request(line: &str, mode: AngleMode) -> Result<Request, BatchError> {
+    let json: Json = serde_json::from_str(line)
+        .map_err(|e| BatchError::Input(format!("Invalid JSON: {}", e)))?;
+    match json {
+        Json::String(expr) => Ok(Request {
+            id: None,
+            expr,
+            mode,
+            vars: vec![],
+        }),
+        Json::Object(object) => Ok(Request {
+            id: object.get("id").cloned(),
+            expr: object
+                .get("expr")
+                .and_then(Json::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| BatchError::Input("Missing string field \"expr\"".to_string()))?,
+            mode: object.get("mode").map_or(Ok(mode), parse_mode)?,
+            vars: object.get("vars").map_or(Ok(vec![]), parse_vars)?,
+        }),
+        _ => Err(BatchError::Input(
+            "Expected an expression string or an object".to_string(),
+        )),
+    }
+}
+
+fn evaluate(request: &Request) -> Result<Option<f64>, BatchError> {
+    let mut env = Environment::new();
+    for (name, value) in &request.vars {
+        if !env.set(name.clone(), VariableValue::Constant(*value)) {
+            return Err(BatchError::Input(format!(
+                "Cannot assign to built-in \"{}\"",
+                name
+            )));
+        }
+    }
+    parser::eval_math(&request.expr, request.mode, &mut env).map_err(BatchError::Calc)
+}
+
+fn error_json(error: BatchError) -> Json {
+    let mut object = Map::new();
+    match error {
+        BatchError::Input(message) => {
+            object.insert("kind".to_string(), "InvalidInput".into());
+            object.insert("message".to_string(), message.into());
+            object.insert("span".to_string(), Json::Null);
+        }
+        BatchError::Calc(error) => {
+            let span = error.span();
+            let mut span_object = Map::new();
+            span_object.insert("start".to_string(), span.start.into());
+            span_object.insert("end".to_string(), span.end.into());
+            object.insert("kind".to_string(), error.kind().into());
+            object.insert("message".to_string(), error.to_string().into());
+            object.insert("span".to_string(), span_object.into());
+        }
+    }
+    object.into()
+}
+
+pub fn eval_line(line: &str, mode: AngleMode) -> Json {
+    let mut object = Map::new();
+    let result = match parse_request(line, mode) {
+        Ok(request) => {
+            if let Some(id) = request.id.clone() {
+                object.insert("id".to_string(), id);
+            }
+            evaluate(&request)
+        }
+        Err(error) => Err(error),
+    };
+    match result {
+        Ok(value) => {
+            let value = value.map_or(Json::Null, |value| value.into());
+            object.insert("value".to_string(), value);
+        }
+        Err(error) => {
+            object.insert("error".to_string(), error_json(error));
+        }
+    }
+    object.into()
+}
+
+pub fn run<R: BufRead, W: Write>(input: R, output: &mut W, mode: AngleMode) -> io::Result<usize> {
+    let mut failed = 0;
+    for (index, line) in input.split(b'\n').enumerate() {
+        let mut result = match String::from_utf8(line?) {
+            Ok(ref line) if line.trim().is_empty() => continue,
+            Ok(line) => eval_line(&line, mode),
+            Err(_) => {
+                let error = BatchError::Input("Line is not valid UTF-8".to_string());
+                let mut object = Map::new();
+                object.insert("error".to_string(), error_json(error));
+                object.into()
+            }
+        };
+        if result.get("error").is_some() {
+            failed += 1;
+        }
+        if let Json::Object(ref mut object) = result {
+            object.insert("line".to_string(), (index + 1).into());
+        }
+        writeln!(output, "{}", result)?;
+    }
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_lines() {
+        let input = [
+            r#""2 + 2""#,
+            r#"{"id": 1, "expr": "sin(x)", "mode": "deg", "vars": {"x": 30}}"#,
+            r#"{"expr": "1 / 0"}"#,
+            r#"{"expr": "f(x) = x"}"#,
+            "",
+            "not json",
+            r#"{"expr": "pi", "vars": {"pi": 3}}"#,
+            r#"{"id": "a", "expr": "x"}"#,
+        ]
+        .join("\n");
+
+        let mut output = vec![];
+        let failed = run(input.as_bytes(), &mut output, AngleMode::Rad).unwrap();
+        let lines = String::from_utf8(output).unwrap();
+        let lines = lines
+            .lines()
+            .map(|line| serde_json::from_str::<Json>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(failed, 4);
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[0]["value"], 4.0);
+        assert_eq!(lines[1]["id"], 1);
+        assert!((lines[1]["value"].as_f64().unwrap() - 0.5).abs() < 1E-9);
+        assert_eq!(lines[2]["error"]["kind"], "DivisionByZero");
+        assert_eq!(lines[2]["error"]["message"], "Division by zero");
+        assert_eq!(lines[2]["error"]["span"]["start"], 0);
+        assert_eq!(lines[2]["error"]["span"]["end"], 5);
+        assert_eq!(lines[3]["value"], Json::Null);
+        assert_eq!(lines[4]["line"], 6);
+        assert_eq!(lines[4]["error"]["kind"], "InvalidInput");
+        assert_eq!(lines[4]["error"]["span"], Json::Null);
+        assert_eq!(lines[5]["error"]["kind"], "InvalidInput");
+        assert_eq!(lines[6]["id"], "a");
+        assert_eq!(lines[6]["error"]["kind"], "UndefinedIdent");
+    }
+
+    #[test]
+    fn batch_invalid_utf8() {
+        let input: &[u8] = b"\"1 + 1\"\r\n\"\xff\"\n\"2\"";
+        let mut output = vec![];
+        let failed = run(input, &mut output, AngleMode::Rad).unwrap();
+        let lines = String::from_utf8(output).unwrap();
+        let lines = lines
+            .lines()
+            .map(|line| serde_json::from_str::<Json>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(failed, 1);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["value"], 2.0);
+        assert_eq!(lines[1]["line"], 2);
+        assert_eq!(lines[1]["error"]["kind"], "InvalidInput");
+        assert_eq!(lines[1]["error"]["message"], "Line is not valid UTF-8");
+        assert_eq!(lines[2]["value"], 2.0);
+    }
+}
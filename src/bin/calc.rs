@@ -0,0 +1,357 @@
+extern crate calculator;
+extern crate rustyline;
+
+use calculator::batch;
+use calculator::parser::{self, AngleMode, CalcError, Environment, VariableValue};
+use calculator::script::Runner;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::{env, process};
+
+const ANS: &str = "ans";
+const PROMPT: &str = "> ";
+const HISTORY_FILE: &str = ".calc_history";
+const USAGE: &str =
+    "usage: calc [--deg | --rad | --grad] [--batch | --script [--keep-going]] [--] [args...]";
+
+fn format_value(value: f64) -> String {
+    if value.abs() < 1E15 {
+        parser::to_fixed(value, 10).to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+struct Session {
+    env: Environment,
+    mode: AngleMode,
+}
+
+impl Session {
+    fn new(mode: AngleMode) -> Self {
+        Self {
+            env: Environment::new(),
+            mode,
+        }
+    }
+
+    fn eval(&mut self, line: &str) -> Result<Option<f64>, CalcError> {
+        let result = parser::eval_math(line, self.mode, &mut self.env)?;
+        if let Some(value) = result {
+            self.env
+                .set(ANS.to_string(), VariableValue::Constant(value));
+        }
+        Ok(result)
+    }
+
+    fn command(&mut self, command: &str) -> Result<Option<String>, String> {
+        match command {
+            "deg" => self.mode = AngleMode::Deg,
+            "rad" => self.mode = AngleMode::Rad,
+            "grad" => self.mode = AngleMode::Grad,
+            "quit" | "q" => return Ok(None),
+            _ => return Err(format!("Unknown command \":{}\"", command)),
+        }
+        Ok(Some(format!("{} mode", self.mode)))
+    }
+
+    fn run_args(&mut self, exprs: &[String]) -> bool {
+        let mut ok = true;
+        for expr in exprs {
+            match self.eval(expr) {
+                Ok(Some(value)) => println!("{}", format_value(value)),
+                Ok(None) => (),
+                Err(e) => {
+                    eprintln!("error: {}: {}", expr, e);
+                    ok = false;
+                }
+            }
+        }
+        ok
+    }
+
+    fn run_stdin(&mut self) -> bool {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        match self.run_lines(stdin.lock(), &mut stdout.lock()) {
+            Ok(failed) => failed == 0,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                false
+            }
+        }
+    }
+
+    fn run_lines<R: BufRead, W: Write>(&mut self, input: R, output: &mut W) -> io::Result<usize> {
+        let mut failed = 0;
+        for (index, line) in input.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            let result = if line.is_empty() {
+                continue;
+            } else if let Some(command) = line.strip_prefix(':') {
+                match self.command(command) {
+                    Ok(Some(_)) => continue,
+                    Ok(None) => break,
+                    Err(e) => Err(e),
+                }
+            } else {
+                self.eval(line).map_err(|e| e.to_string())
+            };
+
+            match result {
+                Ok(Some(value)) => writeln!(output, "{}", format_value(value))?,
+                Ok(None) => (),
+                Err(e) => {
+                    eprintln!("line {}: {}", index + 1, e);
+                    failed += 1;
+                }
+            }
+        }
+        Ok(failed)
+    }
+
+    fn run_repl(&mut self) -> bool {
+        let mut editor = match DefaultEditor::new() {
+            Ok(editor) => editor,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return false;
+            }
+        };
+        let history = history_path();
+        if let Some(path) = &history {
+            let _ = editor.load_history(path);
+        }
+
+        loop {
+            let line = match editor.readline(PROMPT) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    break;
+                }
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let _ = editor.add_history_entry(line);
+
+            if let Some(command) = line.strip_prefix(':') {
+                match self.command(command) {
+                    Ok(Some(message)) => println!("{}", message),
+                    Ok(None) => break,
+                    Err(e) => eprintln!("error: {}", e),
+                }
+                continue;
+            }
+
+            match self.eval(line) {
+                Ok(Some(value)) => println!("{}", format_value(value)),
+                Ok(None) => (),
+                Err(e) => {
+                    let span = e.span();
+                    let start = line.chars().count().min(span.start);
+                    let width = span.end.saturating_sub(span.start).max(1);
+                    eprintln!("{}{}", " ".repeat(PROMPT.len() + start), "^".repeat(width));
+                    eprintln!("error: {}", e);
+                }
+            }
+        }
+
+        if let Some(path) = &history {
+            let _ = editor.save_history(path);
+        }
+        true
+    }
+}
+
+fn run_batch(files: &[String], mode: AngleMode) -> bool {
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    let result = if files.is_empty() {
+        let stdin = io::stdin();
+        let input = stdin.lock();
+        batch::run(input, &mut output, mode)
+    } else {
+        files.iter().try_fold(0, |failed, file| {
+            let input = BufReader::new(File::open(file)?);
+            batch::run(input, &mut output, mode).map(|count| failed + count)
+        })
+    };
+
+    match result {
+        Ok(failed) => failed == 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            false
+        }
+    }
+}
+
+fn run_scripts(files: &[String], mode: AngleMode, keep_going: bool) -> bool {
+    let mut runner = Runner::new(mode, keep_going);
+    let mut ok = true;
+    for file in files {
+        for entry in runner.run_file(Path::new(file)) {
+            match entry {
+                Ok(output) => println!("{}", format_value(output.value)),
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    ok = false;
+                }
+            }
+        }
+        if !ok && !keep_going {
+            break;
+        }
+    }
+    ok
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    mode: AngleMode,
+    is_batch: bool,
+    is_script: bool,
+    keep_going: bool,
+    args: Vec<String>,
+}
+
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut options = Options {
+        mode: AngleMode::Rad,
+        is_batch: false,
+        is_script: false,
+        keep_going: false,
+        args: vec![],
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--deg" => options.mode = AngleMode::Deg,
+            "--rad" => options.mode = AngleMode::Rad,
+            "--grad" => options.mode = AngleMode::Grad,
+            "--batch" => options.is_batch = true,
+            "--script" => options.is_script = true,
+            "--keep-going" => options.keep_going = true,
+            "--" => options.args.extend(args.by_ref()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option \"{}\"", arg)),
+            _ => options.args.push(arg),
+        }
+    }
+    if options.is_batch && options.is_script {
+        return Err("--batch and --script cannot be combined".to_string());
+    }
+    if options.keep_going && !options.is_script {
+        return Err("--keep-going requires --script".to_string());
+    }
+    Ok(options)
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    let (mode, exprs) = (options.mode, &options.args);
+
+    let mut session = Session::new(mode);
+    let ok = if options.is_batch {
+        run_batch(exprs, mode)
+    } else if options.is_script {
+        run_scripts(exprs, mode, options.keep_going)
+    } else if !exprs.is_empty() {
+        session.run_args(exprs)
+    } else if !io::stdin().is_terminal() {
+        session.run_stdin()
+    } else {
+        session.run_repl()
+    };
+
+    if !ok {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn command_line_arguments() {
+        let options = args(&["--deg", "sin(30)", "-1 + 2"]).unwrap();
+        assert_eq!(options.mode, AngleMode::Deg);
+        assert_eq!(options.args, vec!["sin(30)", "-1 + 2"]);
+        assert!(!options.is_batch && !options.is_script);
+
+        let options = args(&["--grad", "--script", "--keep-going", "a.calc"]).unwrap();
+        assert_eq!(options.mode, AngleMode::Grad);
+        assert!(options.is_script && options.keep_going);
+        assert_eq!(options.args, vec!["a.calc"]);
+
+        let options = args(&["--rad", "--", "--2", "--deg"]).unwrap();
+        assert_eq!(options.mode, AngleMode::Rad);
+        assert_eq!(options.args, vec!["--2", "--deg"]);
+
+        assert_eq!(
+            args(&["--degs"]),
+            Err("Unknown option \"--degs\"".to_string())
+        );
+        assert!(args(&["--batch", "--script"]).is_err());
+        assert!(args(&["--keep-going", "1 + 1"]).is_err());
+    }
+
+    #[test]
+    fn session_lines() {
+        let input = "\
+sin(30)
+:deg
+sin(30)
+:grad
+sin(100)
+
+ans * 2
+1 / 0
+:bogus
+f(x) = x^2
+f(3)
+:quit
+2
+";
+        let mut session = Session::new(AngleMode::Rad);
+        let mut output = vec![];
+        let failed = session.run_lines(input.as_bytes(), &mut output).unwrap();
+        assert_eq!(failed, 2);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "-0.9880316241\n0.5\n1\n2\n9\n"
+        );
+        assert_eq!(session.mode, AngleMode::Grad);
+
+        assert_eq!(session.command("rad"), Ok(Some("Rad mode".to_string())));
+        assert_eq!(session.command("q"), Ok(None));
+        assert_eq!(
+            session.command("radians"),
+            Err("Unknown command \":radians\"".to_string())
+        );
+    }
+}
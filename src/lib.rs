@@ -0,0 +1,19 @@
+extern crate bigdecimal;
+#[macro_use]
+extern crate lazy_static;
+extern crate num_bigint;
+extern crate num_complex;
+extern crate num_rational;
+extern crate num_traits;
+#[cfg(feature = "batch")]
+extern crate serde_json;
+
+#[cfg(feature = "batch")]
+pub mod batch;
+pub mod parser;
+pub mod script;
+
+pub use parser::{
+    eval_complex, eval_math, evaluate, simplify, AngleMode, BackendKind, CalcError, Environment,
+    Value,
+};
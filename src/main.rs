@@ -1,11 +1,8 @@
-#[macro_use]
-extern crate lazy_static;
-
+extern crate calculator;
 extern crate gdk;
 extern crate gio;
 extern crate gtk;
 
-mod parser;
 mod window;
 
 use gio::prelude::*;
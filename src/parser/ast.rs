@@ -1,222 +1,1149 @@
 use super::lex::*;
-use super::{AngleMode, VarMap, VariableValue};
-use std::borrow::Cow;
+use super::backend::Backend;
+use super::bound::BoundFunc;
+use super::{AngleMode, Arity, Environment, IntegerMode, VariableValue};
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::Signed;
+use std::fmt;
 
-#[derive(Debug)]
-pub enum Expression {
+pub const MAX_CALL_DEPTH: usize = 128;
+pub const CONDITIONAL: &str = "if";
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
     Binary {
         op: Operator,
         left: Box<Expression>,
         right: Box<Expression>,
     },
     CallExpresion {
-        arg: Box<Expression>,
+        args: Vec<Expression>,
         func: String,
     },
+    Unary {
+        op: Operator,
+        operand: Box<Expression>,
+    },
+    Conditional {
+        condition: Box<Expression>,
+        then: Box<Expression>,
+        otherwise: Box<Expression>,
+    },
+    Bound {
+        func: BoundFunc,
+        var: String,
+        body: Box<Expression>,
+        args: Vec<Expression>,
+    },
+    Angle {
+        unit: AngleMode,
+        operand: Box<Expression>,
+    },
     Number(f64),
+    Complex(Complex64),
+    Rational(BigRational),
+    Variable(String),
     Paren(Box<Expression>),
-    Negation(Box<Expression>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Expression {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+struct Frame<'a, T> {
+    bindings: Vec<(&'a str, T)>,
+    depth: usize,
+}
+
+impl<'a, T> Default for Frame<'a, T> {
+    fn default() -> Self {
+        Self {
+            bindings: vec![],
+            depth: 0,
+        }
+    }
 }
 
 impl Expression {
-    pub fn get_value(&self, mode: AngleMode, context: &VarMap) -> Result<f64, ParseError> {
-        use self::Expression::*;
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
 
-        match self {
-            Binary { op, left, right } => Ok(op.apply(
-                left.get_value(mode.clone(), context)?,
-                right.get_value(mode, context)?,
-            )),
-            Number(value) => Ok(*value),
-            Paren(exp) => exp.get_value(mode, context),
-            CallExpresion { arg, func } => {
-                if let Some(VariableValue::Function(f)) = context.get(&**func) {
-                    arg.get_value(mode.clone(), context).map(|val| f(val, mode))
+    pub fn get_value<B: Backend>(
+        &self,
+        backend: &B,
+        mode: AngleMode,
+        context: &Environment,
+    ) -> Result<B::Value, ParseError> {
+        self.eval(backend, mode, context, &Frame::default())
+    }
+
+    pub fn get_value_at<'a, B: Backend>(
+        &'a self,
+        backend: &B,
+        mode: AngleMode,
+        context: &'a Environment,
+        name: &'a str,
+        value: B::Value,
+    ) -> Result<B::Value, ParseError> {
+        let frame = Frame {
+            bindings: vec![(name, value)],
+            depth: 0,
+        };
+        self.eval(backend, mode, context, &frame)
+    }
+
+    fn eval<'a, B: Backend>(
+        &'a self,
+        backend: &B,
+        mode: AngleMode,
+        context: &'a Environment,
+        frame: &Frame<'a, B::Value>,
+    ) -> Result<B::Value, ParseError> {
+        use self::ExprKind::*;
+
+        match &self.kind {
+            Binary { op, left, right } => {
+                let left = left.eval(backend, mode, context, frame)?;
+                if let Some(value) = backend.to_real(&left).and_then(|l| op.short_circuit(l)) {
+                    return self.real(backend, value);
+                }
+                let right = match &right.kind {
+                    Unary {
+                        op: Operator::Percent,
+                        operand,
+                    } if *op == Operator::Plus || *op == Operator::Minus => {
+                        let value = operand.eval(backend, mode, context, frame)?;
+                        let percent = self.unary(backend, Operator::Percent, value, context)?;
+                        self.binary(backend, Operator::Mult, left.clone(), percent, context)?
+                    }
+                    _ => right.eval(backend, mode, context, frame)?,
+                };
+                self.binary(backend, *op, left, right, context)
+            }
+            Unary { op, operand } => {
+                let value = operand.eval(backend, mode, context, frame)?;
+                self.unary(backend, *op, value, context)
+            }
+            Conditional {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let condition = condition.eval(backend, mode, context, frame)?;
+                let condition = backend
+                    .to_real(&condition)
+                    .ok_or_else(|| ParseError::NonReal(CONDITIONAL.to_string(), self.span))?;
+                if condition != 0.0 {
+                    then.eval(backend, mode, context, frame)
                 } else {
-                    Err(ParseError::NonFunction(func.clone()))
+                    otherwise.eval(backend, mode, context, frame)
+                }
+            }
+            Bound {
+                func,
+                var,
+                body,
+                args,
+            } => {
+                let args = args
+                    .iter()
+                    .map(|arg| {
+                        let value = arg.eval(backend, mode, context, frame)?;
+                        backend
+                            .to_real(&value)
+                            .ok_or_else(|| ParseError::NonReal(func.name().to_string(), self.span))
+                    })
+                    .collect::<Result<Vec<_>, ParseError>>()?;
+                let f = |x: f64| {
+                    let mut bindings = vec![(&var[..], self.real(backend, x)?)];
+                    bindings.extend(frame.bindings.iter().cloned());
+                    let inner = Frame {
+                        bindings,
+                        depth: frame.depth,
+                    };
+                    let value = body.eval(backend, mode, context, &inner)?;
+                    backend
+                        .to_real(&value)
+                        .ok_or_else(|| ParseError::NonReal(func.name().to_string(), self.span))
+                };
+                let value = func.apply(f, &args, body.span, self.span)?;
+                self.real(backend, value)
+            }
+            Angle { unit, operand } => {
+                let value = operand.eval(backend, mode, context, frame)?;
+                if *unit == mode {
+                    return Ok(value);
+                }
+                let factor = self.real(backend, unit.factor(mode))?;
+                self.binary(backend, Operator::Mult, value, factor, context)
+            }
+            Number(value) => self.real(backend, *value),
+            Complex(value) => backend
+                .convert_complex(*value)
+                .ok_or(ParseError::ComplexValue(self.span)),
+            Rational(value) => backend
+                .convert_rational(value)
+                .ok_or_else(|| self.error(EvalError::Overflow)),
+            Variable(name) => match frame.bindings.iter().find(|(param, _)| param == name) {
+                Some((_, value)) => Ok(value.clone()),
+                None => match Scope::global(context).resolve(name, self.span)? {
+                    Ident::Value(expr) => expr.eval(backend, mode, context, frame),
+                    Ident::Function(_) => Err(ParseError::UndefinedIdent(name.clone(), self.span)),
+                },
+            },
+            Paren(exp) => exp.eval(backend, mode, context, frame),
+            CallExpresion { args, func } => {
+                let function = context.get(func);
+                let arity = function
+                    .and_then(VariableValue::arity)
+                    .ok_or_else(|| ParseError::NonFunction(func.clone(), self.span))?;
+                if !arity.accepts(args.len()) {
+                    return Err(ParseError::ArgumentCount {
+                        func: func.clone(),
+                        expected: arity,
+                        found: args.len(),
+                        span: self.span,
+                    });
+                }
+
+                let values = args
+                    .iter()
+                    .map(|arg| arg.eval(backend, mode, context, frame))
+                    .collect::<Result<Vec<_>, ParseError>>()?;
+
+                match function {
+                    Some(VariableValue::UserFunction { params, body }) => {
+                        if frame.depth >= MAX_CALL_DEPTH {
+                            return Err(ParseError::RecursionLimit(func.clone(), self.span));
+                        }
+                        let inner = Frame {
+                            bindings: params.iter().map(|p| &p[..]).zip(values).collect(),
+                            depth: frame.depth + 1,
+                        };
+                        body.eval(backend, mode, context, &inner)
+                    }
+                    Some(VariableValue::Function(_, f)) => {
+                        if let Some(value) = backend.call(func, &values, mode) {
+                            return self.finite(backend, value);
+                        }
+                        let real = values
+                            .iter()
+                            .map(|value| backend.to_real(value))
+                            .collect::<Option<Vec<f64>>>()
+                            .ok_or_else(|| ParseError::NonReal(func.clone(), self.span))?;
+                        let value = f(&real, mode).map_err(|e| self.error(e))?;
+                        self.checked(backend, func, real.first().cloned(), value)
+                    }
+                    _ => Err(ParseError::NonFunction(func.clone(), self.span)),
                 }
             }
-            Negation(exp) => exp.get_value(mode, context).map(|v| -v),
         }
     }
 
-    pub fn negate(mut self, neg: usize) -> Expression {
-        for _ in 0..neg {
-            self = Expression::Negation(self.into());
+    fn error(&self, error: EvalError) -> ParseError {
+        ParseError::Eval(error, self.span)
+    }
+
+    fn real<B: Backend>(&self, backend: &B, value: f64) -> Result<B::Value, ParseError> {
+        Some(value)
+            .filter(|value| value.is_finite())
+            .and_then(|value| backend.convert_real(value))
+            .ok_or_else(|| self.error(EvalError::Overflow))
+    }
+
+    fn finite<B: Backend>(&self, backend: &B, value: B::Value) -> Result<B::Value, ParseError> {
+        if backend.is_finite(&value) {
+            Ok(value)
+        } else {
+            Err(self.error(EvalError::Overflow))
         }
-        self
     }
 
-    pub fn non_neg(self) -> (Expression, usize) {
-        let mut expr = self;
-        let mut level = 0;
-        while let Expression::Negation(inner) = expr {
-            expr = *inner;
-            level += 1;
+    fn checked<B: Backend>(
+        &self,
+        backend: &B,
+        name: &str,
+        arg: Option<f64>,
+        value: f64,
+    ) -> Result<B::Value, ParseError> {
+        if value.is_nan() {
+            let arg = arg.unwrap_or(value);
+            Err(self.error(EvalError::Domain(name.to_string(), arg)))
+        } else {
+            self.real(backend, value)
+        }
+    }
+
+    fn binary<B: Backend>(
+        &self,
+        backend: &B,
+        op: Operator,
+        left: B::Value,
+        right: B::Value,
+        context: &Environment,
+    ) -> Result<B::Value, ParseError> {
+        let real = (backend.to_real(&left), backend.to_real(&right));
+        if op.divides_by_zero(real.0, real.1) {
+            return Err(self.error(EvalError::DivisionByZero));
+        }
+        if backend.is_too_large(op, &left, &right) {
+            return Err(self.error(EvalError::Overflow));
+        }
+        match real {
+            (Some(l), Some(r)) if op.is_bitwise() => {
+                let value = eval_integer(op, l, r, context.integer_mode(), self.span)?;
+                self.real(backend, value)
+            }
+            _ => match backend.apply(op, &left, &right) {
+                Some(value) => self.finite(backend, value),
+                None => match real {
+                    (Some(l), Some(r)) => {
+                        let value = op.apply(l, r);
+                        if value == 0.0 && l != 0.0 && r != 0.0 && op.can_underflow() {
+                            return Err(self.error(EvalError::Underflow));
+                        }
+                        self.checked(backend, op.symbol(), Some(l), value)
+                    }
+                    _ => Err(ParseError::NonReal(op.symbol().to_string(), self.span)),
+                },
+            },
+        }
+    }
+
+    fn unary<B: Backend>(
+        &self,
+        backend: &B,
+        op: Operator,
+        value: B::Value,
+        context: &Environment,
+    ) -> Result<B::Value, ParseError> {
+        let real = backend.to_real(&value);
+        match real {
+            Some(v) if op.is_bitwise() => {
+                let value = eval_integer(op, v, 0.0, context.integer_mode(), self.span)?;
+                self.real(backend, value)
+            }
+            _ => match backend.apply_unary(op, &value) {
+                Some(value) => self.finite(backend, value),
+                None => match real {
+                    Some(v) => {
+                        let value = op.apply_unary(v).ok_or_else(|| {
+                            self.error(EvalError::Domain(op.symbol().to_string(), v))
+                        })?;
+                        self.checked(backend, op.symbol(), Some(v), value)
+                    }
+                    None => Err(ParseError::NonReal(op.symbol().to_string(), self.span)),
+                },
+            },
+        }
+    }
+}
+
+impl Expression {
+    fn precedence(&self) -> OperatorPrecedence {
+        use self::OperatorPrecedence::*;
+
+        match &self.kind {
+            ExprKind::Binary { op, .. } | ExprKind::Unary { op, .. } => op.precedence(),
+            ExprKind::Number(value) if *value < 0.0 => Negation,
+            ExprKind::Complex(value) if value.re != 0.0 => PlusMinus,
+            ExprKind::Complex(value) if value.im < 0.0 => Negation,
+            ExprKind::Complex(_) => MultDiv,
+            ExprKind::Rational(value) if value.is_negative() => Negation,
+            ExprKind::Rational(value) if !value.is_integer() => MultDiv,
+            _ => Postfix,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut fmt::Formatter, parens: bool) -> fmt::Result {
+        if parens {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ExprKind::*;
+
+        match &self.kind {
+            Binary { op, left, right } => {
+                let precedence = op.precedence();
+                let associativity = op
+                    .info()
+                    .map_or(Associativity::Left, |info| info.associativity);
+                let left_parens = left.precedence() < precedence
+                    || (left.precedence() == precedence && associativity != Associativity::Left);
+                let right_parens = right.precedence() < precedence
+                    || (right.precedence() == precedence && associativity != Associativity::Right);
+
+                left.fmt_operand(f, left_parens)?;
+                if *op == Operator::Exp {
+                    write!(f, "{}", op.symbol())?;
+                } else {
+                    write!(f, " {} ", op.symbol())?;
+                }
+                right.fmt_operand(f, right_parens)
+            }
+            Unary { op, operand } => match op.info().map(|info| info.fixity) {
+                Some(Fixity::Postfix) => {
+                    operand.fmt_operand(f, operand.precedence() < op.precedence())?;
+                    write!(f, "{}", op.symbol())
+                }
+                _ => {
+                    let separator = if op.symbol().chars().all(char::is_alphabetic) {
+                        " "
+                    } else {
+                        ""
+                    };
+                    write!(f, "{}{}", op.symbol(), separator)?;
+                    operand.fmt_operand(f, operand.precedence() <= op.precedence())
+                }
+            },
+            Conditional {
+                condition,
+                then,
+                otherwise,
+            } => write!(f, "{}({}, {}, {})", CONDITIONAL, condition, then, otherwise),
+            Bound {
+                func,
+                var,
+                body,
+                args,
+            } => {
+                write!(f, "{}({}, {}", func.name(), body, var)?;
+                for arg in args {
+                    write!(f, ", {}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Angle { unit, operand } => {
+                operand.fmt_operand(f, operand.precedence() < OperatorPrecedence::Postfix)?;
+                let suffix = unit.suffix();
+                let separator = if suffix.chars().all(char::is_alphabetic) {
+                    " "
+                } else {
+                    ""
+                };
+                write!(f, "{}{}", separator, suffix)
+            }
+            Number(value) => write!(f, "{}", value),
+            Complex(value) => {
+                let im = match value.im.abs() {
+                    1.0 => String::new(),
+                    im => im.to_string(),
+                };
+                let sign = if value.im < 0.0 { "-" } else { "+" };
+                if value.re == 0.0 {
+                    write!(f, "{}{}i", if value.im < 0.0 { "-" } else { "" }, im)
+                } else {
+                    write!(f, "{} {} {}i", value.re, sign, im)
+                }
+            }
+            Rational(value) => write!(f, "{}", value),
+            Variable(name) => write!(f, "{}", name),
+            Paren(inner) => write!(f, "({})", inner),
+            CallExpresion { args, func } => {
+                write!(f, "{}(", func)?;
+                for (index, arg) in args.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+pub fn eval_integer(
+    op: Operator,
+    left: f64,
+    right: f64,
+    mode: IntegerMode,
+    span: Span,
+) -> Result<f64, ParseError> {
+    let domain =
+        |value: f64| ParseError::Eval(EvalError::Domain(op.symbol().to_string(), value), span);
+    let integer = |value: f64| mode.to_integer(value).ok_or_else(|| domain(value));
+    let is_shift = matches!(op, Operator::ShiftLeft | Operator::ShiftRight);
+    if is_shift && right >= f64::from(mode.size.bits()) {
+        return Err(ParseError::Eval(EvalError::Overflow, span));
+    }
+    let value = op
+        .apply_integer(integer(left)?, integer(right)?)
+        .map(|value| mode.wrap(value))
+        .ok_or_else(|| domain(right))?;
+    if super::is_exact(value) {
+        Ok(value as f64)
+    } else {
+        Err(ParseError::Eval(EvalError::InexactInteger, span))
+    }
+}
+
+#[derive(Debug)]
+pub enum Statement {
+    Assignment {
+        name: String,
+        span: Span,
+        value: Expression,
+    },
+    Definition {
+        name: String,
+        span: Span,
+        params: Vec<String>,
+        body: Expression,
+    },
+    Expression(Expression),
+}
+
+impl Statement {
+    pub fn execute<B: Backend>(
+        self,
+        backend: &B,
+        mode: AngleMode,
+        context: &mut Environment,
+    ) -> Result<Option<B::Value>, ParseError> {
+        let (name, span, value, result) = match self {
+            Statement::Assignment { name, span, value } => {
+                let value = value.get_value(backend, mode, context)?;
+                (name, span, backend.to_variable(value.clone()), Some(value))
+            }
+            Statement::Definition {
+                name,
+                span,
+                params,
+                body,
+            } => (
+                name,
+                span,
+                VariableValue::UserFunction { params, body },
+                None,
+            ),
+            Statement::Expression(expr) => return expr.get_value(backend, mode, context).map(Some),
+        };
+
+        if context.set(name.clone(), value) {
+            Ok(result)
+        } else {
+            Err(ParseError::BuiltinAssignment(name, span))
+        }
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Statement::Assignment { name, value, .. } => write!(f, "{} = {}", name, value),
+            Statement::Definition {
+                name, params, body, ..
+            } => write!(f, "{}({}) = {}", name, params.join(", "), body),
+            Statement::Expression(expr) => write!(f, "{}", expr),
         }
-        (expr, level)
     }
 }
 
 #[derive(Debug)]
 pub enum ParseError {
-    UnexpectedEOF,
-    UndefinedIdent(String),
+    UnexpectedEOF(Span),
+    UndefinedIdent(String, Span),
     UnexpectedToken(Token),
-    NonFunction(String),
+    NonFunction(String, Span),
+    BuiltinAssignment(String, Span),
+    RecursionLimit(String, Span),
+    NonReal(String, Span),
+    ComplexValue(Span),
+    NonDifferentiable(String, Span),
+    NoConvergence(String, Span),
+    IterationLimit(String, Span),
+    Eval(EvalError, Span),
+    ArgumentCount {
+        func: String,
+        expected: Arity,
+        found: usize,
+        span: Span,
+    },
 }
 
-impl From<ParseError> for Cow<'static, str> {
-    fn from(e: ParseError) -> Self {
+impl ParseError {
+    pub fn span(&self) -> Span {
         use self::ParseError::*;
 
-        match e {
-            UnexpectedEOF => Cow::Borrowed("Unexpected end of file"),
-            UnexpectedToken(token) => Cow::Owned(format!("Unexpected {}", token.get_descriptor())),
-            UndefinedIdent(ident) => Cow::Owned(format!("Undefined variable \"{}\"", ident)),
-            NonFunction(ident) => Cow::Owned(format!("\"{}\" is not a function", ident)),
+        match self {
+            UnexpectedToken(token) => token.span,
+            UnexpectedEOF(span)
+            | UndefinedIdent(_, span)
+            | NonFunction(_, span)
+            | BuiltinAssignment(_, span)
+            | RecursionLimit(_, span)
+            | NonReal(_, span)
+            | ComplexValue(span)
+            | NonDifferentiable(_, span)
+            | NoConvergence(_, span)
+            | IterationLimit(_, span)
+            | Eval(_, span)
+            | ArgumentCount { span, .. } => *span,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EvalError {
+    DivisionByZero,
+    Domain(String, f64),
+    Overflow,
+    Underflow,
+    InexactInteger,
+}
+
+#[derive(Clone, Copy)]
+pub struct Scope<'a> {
+    variables: &'a Environment,
+    params: &'a [String],
+    function: Option<&'a str>,
+}
+
+impl<'a> Scope<'a> {
+    pub fn global(variables: &'a Environment) -> Self {
+        Self {
+            variables,
+            params: &[],
+            function: None,
+        }
+    }
+
+    fn is_defined(&self, ident: &str) -> bool {
+        self.params.iter().any(|param| param == ident)
+            || self.function == Some(ident)
+            || self.variables.get(ident).is_some()
+    }
+
+    // User variables in function bodies are looked up when the function is called
+    fn is_user_value(&self, ident: &str) -> bool {
+        !self.variables.is_builtin(ident)
+            && self
+                .variables
+                .get(ident)
+                .is_some_and(|value| value.arity().is_none())
+    }
+
+    fn split_trailing_digits(&self, ident: &str) -> Option<(String, f64)> {
+        let prefix = ident.trim_end_matches(|c: char| c.is_ascii_digit());
+        Some(prefix)
+            .filter(|prefix| prefix.len() < ident.len() && !self.is_defined(ident))
+            .filter(|prefix| self.is_defined(prefix))
+            .and_then(|prefix| {
+                ident[prefix.len()..]
+                    .parse()
+                    .ok()
+                    .map(|num| (prefix.to_string(), num))
+            })
+    }
+
+    fn resolve(&self, ident: &str, span: Span) -> Result<Ident, ParseError> {
+        if self.params.iter().any(|param| param == ident) {
+            let kind = ExprKind::Variable(ident.to_string());
+            Ok(Ident::Value(Expression::new(kind, span)))
+        } else if self.function == Some(ident) {
+            Ok(Ident::Function(Arity::Fixed(self.params.len())))
+        } else if self.function.is_some() && self.is_user_value(ident) {
+            let kind = ExprKind::Variable(ident.to_string());
+            Ok(Ident::Value(Expression::new(kind, span)))
+        } else {
+            match self.variables.get(ident) {
+                Some(VariableValue::Constant(num)) => {
+                    Ok(Ident::Value(Expression::new(ExprKind::Number(*num), span)))
+                }
+                Some(VariableValue::Complex(num)) => {
+                    Ok(Ident::Value(Expression::new(ExprKind::Complex(*num), span)))
+                }
+                Some(VariableValue::Rational(num)) => Ok(Ident::Value(Expression::new(
+                    ExprKind::Rational(num.clone()),
+                    span,
+                ))),
+                Some(value) => value
+                    .arity()
+                    .map(Ident::Function)
+                    .ok_or_else(|| ParseError::NonFunction(ident.to_string(), span)),
+                None => Err(ParseError::UndefinedIdent(ident.to_string(), span)),
+            }
         }
     }
 }
 
-#[derive(Debug)]
-struct ContextualizedTokens {
-    expressions: Vec<Expression>,
-    operators: Vec<Operator>,
+enum Ident {
+    Value(Expression),
+    Function(Arity),
 }
 
-impl ContextualizedTokens {
-    fn from(variables: &VarMap, arr: Vec<Token>) -> Result<ContextualizedTokens, ParseError> {
-        let mut expressions = vec![];
-        let mut operators = vec![];
+fn split_args(tokens: Vec<Token>) -> Vec<Vec<Token>> {
+    let mut args = vec![vec![]];
+    for token in tokens {
+        if token.is_comma() {
+            args.push(vec![]);
+        } else if let Some(arg) = args.last_mut() {
+            arg.push(token);
+        }
+    }
+    args
+}
 
-        let mut negation_stack = 0;
-        let mut func: Option<String> = None;
-        let mut last_paren = false;
-        let mut last_op = false;
+struct Parser<'a> {
+    tokens: ::std::vec::IntoIter<Token>,
+    scope: Scope<'a>,
+    end: Span,
+}
 
-        for token in arr.into_iter() {
-            match token {
-                Token::Number(num)
-                    if func.is_none() && (last_op || last_paren || expressions.is_empty()) =>
-                {
-                    if expressions.len() != operators.len() {
-                        operators.push(Operator::Mult);
-                    }
-                    expressions.push(Expression::Number(num).negate(negation_stack));
-                    last_op = false;
-                    last_paren = false;
-                    negation_stack = 0;
-                }
-                Token::Negation if func.is_none() => negation_stack += 1,
-                Token::Op(ref op) if func.is_none() && !last_op => {
-                    operators.push(op.clone());
-                    last_op = true;
-                    last_paren = false;
-                }
-                Token::Parentheses(paren) => {
-                    if expressions.len() != operators.len() {
-                        operators.push(Operator::Mult);
-                    }
-                    if let Some(func) = func.take() {
-                        expressions.push(
-                            Expression::CallExpresion {
-                                func,
-                                arg: ast_gen(paren, variables)?.into(),
-                            }.negate(negation_stack),
-                        )
+impl<'a> Parser<'a> {
+    fn new(tokens: Vec<Token>, scope: Scope<'a>) -> Self {
+        let end = tokens.last().map(|t| t.span).unwrap_or_default();
+        Self {
+            tokens: tokens.into_iter(),
+            scope,
+            end,
+        }
+    }
+
+    fn next(&mut self) -> Result<Token, ParseError> {
+        self.tokens
+            .next()
+            .ok_or(ParseError::UnexpectedEOF(self.end))
+    }
+
+    fn peek(&self, offset: usize) -> Option<&Token> {
+        self.tokens.as_slice().get(offset)
+    }
+
+    fn full_expression(&mut self) -> Result<Expression, ParseError> {
+        self.expression(OperatorPrecedence::VALUES[0])
+    }
+
+    fn parse(mut self) -> Result<Expression, ParseError> {
+        let expr = self.full_expression()?;
+        match self.tokens.next() {
+            Some(token) => Err(ParseError::UnexpectedToken(token)),
+            None => Ok(expr),
+        }
+    }
+
+    fn expression(&mut self, min: OperatorPrecedence) -> Result<Expression, ParseError> {
+        let mut left = self.prefix()?;
+        let mut last: Option<&'static OperatorInfo> = None;
+
+        loop {
+            if let Some(unit) = self.angle_unit() {
+                let token = self.next()?;
+                let span = left.span.to(token.span);
+                left = Expression::new(
+                    ExprKind::Angle {
+                        unit,
+                        operand: left.into(),
+                    },
+                    span,
+                );
+                continue;
+            }
+
+            let info = match self.peek(0).map(|t| &t.kind) {
+                Some(TokenKind::Op(symbol)) => {
+                    let postfix = OperatorInfo::find(symbol, Fixity::Postfix);
+                    let infix = OperatorInfo::find(symbol, Fixity::Infix);
+                    if self.peek(1).is_some_and(Token::starts_operand) {
+                        infix.or(postfix)
                     } else {
-                        expressions.push(Expression::Paren(
-                            ast_gen(paren, variables)?.negate(negation_stack).into(),
-                        ));
+                        postfix.or(infix)
                     }
-                    negation_stack = 0;
-                    last_op = false;
-                    last_paren = true;
                 }
-                Token::Var(ref ident) if func.is_none() => match variables
-                    .get(&ident[..])
-                    .ok_or(ParseError::UndefinedIdent(ident.clone()))?
+                Some(TokenKind::Number(_))
+                | Some(TokenKind::Var(_))
+                | Some(TokenKind::OpenParen) => Operator::Mult.info(),
+                _ => break,
+            };
+            let info = match info {
+                Some(info) if info.precedence >= min => info,
+                _ => break,
+            };
+
+            if let Some(prev) = last {
+                if info.precedence == prev.precedence
+                    && info.associativity == Associativity::Neither
                 {
-                    VariableValue::Constant(num) => {
-                        if expressions.len() != operators.len() {
-                            operators.push(Operator::Mult);
-                        }
-                        expressions.push(Expression::Number(*num));
-                        last_op = false;
-                        last_paren = true;
-                    }
-                    VariableValue::Function(_) => {
-                        func = Some(ident.clone());
-                    }
-                },
-                _ => return Err(ParseError::UnexpectedToken(token)),
-            }
-        }
-
-        Some(Self {
-            expressions,
-            operators,
-        }).filter(|tokens| {
-            tokens
-                .expressions
-                .len()
-                .checked_sub(tokens.operators.len())
-                .filter(|diff| *diff == 1)
-                .is_some()
-        }).ok_or(ParseError::UnexpectedEOF)
-    }
-
-    fn reduce_at(&mut self, ind: usize, is_exp: bool) {
-        if ind + 1 < self.expressions.len() && ind < self.operators.len() {
-            let op = self.operators.remove(ind);
-            let left = self.expressions.remove(ind);
-            let right = self.expressions.remove(ind).into();
-            let expr = if is_exp {
-                let (left, level) = left.non_neg();
-                Expression::Binary {
-                    op,
-                    left: left.into(),
-                    right,
-                }.negate(level)
-            } else {
-                Expression::Binary {
-                    op,
-                    left: left.into(),
-                    right,
+                    return Err(ParseError::UnexpectedToken(self.next()?));
                 }
+            }
+
+            let op_span = match self.peek(0) {
+                Some(Token {
+                    kind: TokenKind::Op(_),
+                    span,
+                }) => Some(*span),
+                _ => None,
             };
-            self.expressions.insert(ind, expr);
+            if op_span.is_some() {
+                self.tokens.next();
+            }
+
+            left = if info.fixity == Fixity::Postfix {
+                let span = left.span.to(op_span.unwrap_or(left.span));
+                Expression::new(
+                    ExprKind::Unary {
+                        op: info.op,
+                        operand: left.into(),
+                    },
+                    span,
+                )
+            } else {
+                let right = self.expression(Self::right_precedence(info))?;
+                let span = left.span.to(right.span);
+                Expression::new(
+                    ExprKind::Binary {
+                        op: info.op,
+                        left: left.into(),
+                        right: right.into(),
+                    },
+                    span,
+                )
+            };
+            last = Some(info);
         }
+
+        Ok(left)
     }
 
-    pub fn into_ast(mut self) -> Result<Expression, ParseError> {
-        if self.expressions.len() != self.operators.len() + 1 {
-            return Err(ParseError::UnexpectedEOF);
+    fn angle_unit(&self) -> Option<AngleMode> {
+        match self.peek(0).map(|t| &t.kind) {
+            Some(TokenKind::Degree) => Some(AngleMode::Deg),
+            Some(TokenKind::Var(ident)) if !self.scope.is_defined(ident) => {
+                AngleMode::from_suffix(ident)
+            }
+            _ => None,
+        }
+    }
+
+    fn right_precedence(info: &OperatorInfo) -> OperatorPrecedence {
+        match info.associativity {
+            Associativity::Right => info.precedence,
+            Associativity::Left | Associativity::Neither => OperatorPrecedence::VALUES
+                .iter()
+                .cloned()
+                .find(|prec| *prec > info.precedence)
+                .unwrap_or(info.precedence),
         }
+    }
 
-        for prec in OperatorPrecedence::VALUES.iter().rev() {
-            let instances = (0..self.operators.len())
-                .filter(|i| {
-                    self.operators
-                        .get(*i)
-                        .filter(|op| op.precedence() == *prec)
-                        .is_some()
-                }).enumerate()
-                .flat_map(|(index, ind)| ind.checked_sub(index))
-                .collect::<Vec<usize>>();
-            for index in instances {
-                self.reduce_at(index, *prec == OperatorPrecedence::Exp);
+    fn prefix(&mut self) -> Result<Expression, ParseError> {
+        let token = self.next()?;
+        match token.kind {
+            TokenKind::Op(symbol) => match OperatorInfo::find(symbol, Fixity::Prefix) {
+                Some(info) => {
+                    let operand = self.expression(info.precedence)?;
+                    let span = token.span.to(operand.span);
+                    Ok(Expression::new(
+                        ExprKind::Unary {
+                            op: info.op,
+                            operand: operand.into(),
+                        },
+                        span,
+                    ))
+                }
+                None => Err(ParseError::UnexpectedToken(Token::new(
+                    token.kind, token.span,
+                ))),
+            },
+            TokenKind::Number(num) => Ok(Expression::new(ExprKind::Number(num), token.span)),
+            TokenKind::OpenParen => {
+                let inner = self.full_expression()?;
+                let close = self.close_paren()?;
+                Ok(Expression::new(
+                    ExprKind::Paren(inner.into()),
+                    token.span.to(close),
+                ))
             }
+            TokenKind::Var(ref ident) if ident == CONDITIONAL => self.conditional(token.span),
+            TokenKind::Var(ident) => match BoundFunc::from_name(&ident) {
+                Some(func) => self.bound(func, token.span),
+                None => match self.scope.resolve(&ident, token.span)? {
+                    Ident::Value(expr) => Ok(expr),
+                    Ident::Function(arity) => self.call(ident, arity, token.span),
+                },
+            },
+            kind => Err(ParseError::UnexpectedToken(Token::new(kind, token.span))),
         }
+    }
 
-        self.expressions
-            .pop()
-            .filter(|_| self.expressions.len() == 0 && self.operators.len() == 0)
-            .ok_or(ParseError::UnexpectedEOF)
+    fn close_paren(&mut self) -> Result<Span, ParseError> {
+        let token = self.next()?;
+        match token.kind {
+            TokenKind::CloseParen => Ok(token.span),
+            kind => Err(ParseError::UnexpectedToken(Token::new(kind, token.span))),
+        }
     }
+
+    fn arguments(
+        &mut self,
+        func: &str,
+        arity: Arity,
+        start: Span,
+    ) -> Result<(Vec<Expression>, Span), ParseError> {
+        let token = self.tokens.next().ok_or(ParseError::UnexpectedEOF(start))?;
+        if !token.is_open_paren() {
+            return Err(ParseError::UnexpectedToken(token));
+        }
+
+        let mut args = vec![self.full_expression()?];
+        while self.peek(0).is_some_and(Token::is_comma) {
+            self.next()?;
+            args.push(self.full_expression()?);
+        }
+        let span = start.to(self.close_paren()?);
+
+        if arity.accepts(args.len()) {
+            Ok((args, span))
+        } else {
+            Err(ParseError::ArgumentCount {
+                func: func.to_string(),
+                expected: arity,
+                found: args.len(),
+                span,
+            })
+        }
+    }
+
+    fn call(&mut self, func: String, arity: Arity, start: Span) -> Result<Expression, ParseError> {
+        let (args, span) = self.arguments(&func, arity, start)?;
+        Ok(Expression::new(
+            ExprKind::CallExpresion { func, args },
+            span,
+        ))
+    }
+
+    fn conditional(&mut self, start: Span) -> Result<Expression, ParseError> {
+        let (args, span) = self.arguments(CONDITIONAL, Arity::Fixed(3), start)?;
+        let mut args = args.into_iter().map(Box::new);
+        match (args.next(), args.next(), args.next()) {
+            (Some(condition), Some(then), Some(otherwise)) => Ok(Expression::new(
+                ExprKind::Conditional {
+                    condition,
+                    then,
+                    otherwise,
+                },
+                span,
+            )),
+            _ => Err(ParseError::UnexpectedEOF(span)),
+        }
+    }
+
+    fn bound(&mut self, func: BoundFunc, start: Span) -> Result<Expression, ParseError> {
+        let token = self.tokens.next().ok_or(ParseError::UnexpectedEOF(start))?;
+        if !token.is_open_paren() {
+            return Err(ParseError::UnexpectedToken(token));
+        }
+
+        let mut depth = 0;
+        let end = self.tokens.as_slice().iter().position(|token| {
+            match token.kind {
+                TokenKind::OpenParen => depth += 1,
+                TokenKind::CloseParen => depth -= 1,
+                _ => (),
+            }
+            depth < 0 || (depth == 0 && token.is_comma())
+        });
+        let body: Vec<Token> = match end {
+            Some(end) => self.tokens.by_ref().take(end).collect(),
+            None => return Err(ParseError::UnexpectedEOF(self.end)),
+        };
+
+        let token = self.next()?;
+        if body.is_empty() || !token.is_comma() {
+            let span = start.to(token.span);
+            return Err(match token.kind {
+                TokenKind::CloseParen if !body.is_empty() => ParseError::ArgumentCount {
+                    func: func.name().to_string(),
+                    expected: func.arity(),
+                    found: 1,
+                    span,
+                },
+                _ => ParseError::UnexpectedToken(token),
+            });
+        }
+
+        let token = self.next()?;
+        let var = match token.kind {
+            TokenKind::Var(ref name) if self.scope.variables.is_builtin(name) => {
+                return Err(ParseError::BuiltinAssignment(name.clone(), token.span))
+            }
+            TokenKind::Var(name) => name,
+            kind => return Err(ParseError::UnexpectedToken(Token::new(kind, token.span))),
+        };
+        let mut params = self.scope.params.to_vec();
+        params.push(var.clone());
+        let body = ast_gen(
+            body,
+            Scope {
+                params: &params,
+                ..self.scope
+            },
+        )?;
+
+        let mut args = vec![];
+        while self.peek(0).is_some_and(Token::is_comma) {
+            self.next()?;
+            args.push(self.full_expression()?);
+        }
+        let span = start.to(self.close_paren()?);
+        if !func.arity().accepts(args.len() + 2) {
+            return Err(ParseError::ArgumentCount {
+                func: func.name().to_string(),
+                expected: func.arity(),
+                found: args.len() + 2,
+                span,
+            });
+        }
+
+        Ok(Expression::new(
+            ExprKind::Bound {
+                func,
+                var,
+                body: body.into(),
+                args,
+            },
+            span,
+        ))
+    }
+}
+
+pub fn ast_gen(tokens: Vec<Token>, scope: Scope) -> Result<Expression, ParseError> {
+    let mut split = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        let parts = match token.kind {
+            TokenKind::Var(ref ident) => scope.split_trailing_digits(ident),
+            _ => None,
+        };
+        if let Some((prefix, num)) = parts {
+            let mid = token.span.start + prefix.chars().count();
+            split.push(Token::new(
+                TokenKind::Var(prefix),
+                Span::new(token.span.start, mid),
+            ));
+            split.push(Token::new(
+                TokenKind::Number(num),
+                Span::new(mid, token.span.end),
+            ));
+        } else {
+            split.push(token);
+        }
+    }
+
+    Parser::new(split, scope).parse()
+}
+
+fn param_gen(
+    tokens: Vec<Token>,
+    span: Span,
+    variables: &Environment,
+) -> Result<Vec<String>, ParseError> {
+    split_args(tokens)
+        .into_iter()
+        .map(|param| {
+            let mut param = param.into_iter();
+            match (param.next(), param.next()) {
+                (Some(token), None) => match token.kind {
+                    TokenKind::Var(ref name) if variables.is_builtin(name) => {
+                        Err(ParseError::BuiltinAssignment(name.clone(), token.span))
+                    }
+                    TokenKind::Var(name) => Ok(name),
+                    kind => Err(ParseError::UnexpectedToken(Token::new(kind, token.span))),
+                },
+                (Some(ref first), Some(token)) if first.is_var() => {
+                    Err(ParseError::UnexpectedToken(token))
+                }
+                (Some(token), _) => Err(ParseError::UnexpectedToken(token)),
+                (None, _) => Err(ParseError::UnexpectedEOF(span)),
+            }
+        }).collect()
+}
+
+pub fn expression_gen(
+    tokens: Vec<Token>,
+    params: &[String],
+    variables: &Environment,
+) -> Result<Expression, ParseError> {
+    let scope = Scope {
+        variables,
+        params,
+        function: None,
+    };
+    ast_gen(tokens, scope)
 }
 
-pub fn ast_gen(tokens: Vec<Token>, variables: &VarMap) -> Result<Expression, ParseError> {
-    ContextualizedTokens::from(variables, tokens)?.into_ast()
+pub fn statement_gen(
+    mut tokens: Vec<Token>,
+    variables: &Environment,
+) -> Result<Statement, ParseError> {
+    let is_target = |ind: usize, check: fn(&Token) -> bool| tokens.get(ind).is_some_and(check);
+    let split = if !is_target(0, Token::is_var) {
+        None
+    } else if is_target(1, Token::is_assign) {
+        Some(2)
+    } else if is_target(1, Token::is_open_paren) {
+        tokens
+            .iter()
+            .position(Token::is_close_paren)
+            .filter(|close| is_target(close + 1, Token::is_assign))
+            .map(|close| close + 2)
+    } else {
+        None
+    };
+
+    let split = match split {
+        Some(split) => split,
+        None => return ast_gen(tokens, Scope::global(variables)).map(Statement::Expression),
+    };
+
+    let body = tokens.split_off(split);
+    let mut head = tokens.into_iter();
+
+    let (name, span) = match head.next() {
+        Some(Token {
+            kind: TokenKind::Var(name),
+            span,
+        }) => (name, span),
+        _ => return Err(ParseError::UnexpectedEOF(Span::default())),
+    };
+    if variables.is_builtin(&name) {
+        return Err(ParseError::BuiltinAssignment(name, span));
+    }
+
+    if split == 2 {
+        let value = ast_gen(body, Scope::global(variables))?;
+        return Ok(Statement::Assignment { name, span, value });
+    }
+
+    let params_start = head.next().map_or(span, |open| open.span);
+    let params = head.by_ref().take(split - 4).collect();
+    let params_span = head
+        .next()
+        .map_or(span, |close| params_start.to(close.span));
+    let params = param_gen(params, params_span, variables)?;
+    let body = ast_gen(
+        body,
+        Scope {
+            variables,
+            params: &params,
+            function: Some(&name),
+        },
+    )?;
+    Ok(Statement::Definition {
+        name,
+        span,
+        params,
+        body,
+    })
 }
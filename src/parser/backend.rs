@@ -0,0 +1,108 @@
+use super::complex::format_complex;
+use super::exact::{format_decimal, format_rational};
+use super::lex::Operator;
+use super::{AngleMode, ComplexFormat, VariableValue};
+use bigdecimal::BigDecimal;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use std::fmt::{self, Debug, Display};
+
+pub trait Backend {
+    type Value: Clone + Debug + PartialEq;
+
+    fn convert_real(&self, value: f64) -> Option<Self::Value>;
+    fn convert_complex(&self, value: Complex64) -> Option<Self::Value>;
+    fn convert_rational(&self, value: &BigRational) -> Option<Self::Value>;
+    fn to_real(&self, value: &Self::Value) -> Option<f64>;
+    fn is_finite(&self, _: &Self::Value) -> bool {
+        true
+    }
+    fn is_too_large(&self, _: Operator, _: &Self::Value, _: &Self::Value) -> bool {
+        false
+    }
+    fn apply(&self, op: Operator, left: &Self::Value, right: &Self::Value) -> Option<Self::Value>;
+    fn apply_unary(&self, op: Operator, value: &Self::Value) -> Option<Self::Value>;
+    fn call(&self, func: &str, args: &[Self::Value], mode: AngleMode) -> Option<Self::Value>;
+    fn to_variable(&self, value: Self::Value) -> VariableValue;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BackendKind {
+    #[default]
+    Float,
+    Complex,
+    Rational,
+    Decimal(u64),
+}
+
+impl Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackendKind::Float => write!(f, "float"),
+            BackendKind::Complex => write!(f, "complex"),
+            BackendKind::Rational => write!(f, "rational"),
+            BackendKind::Decimal(precision) => write!(f, "decimal({})", precision),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Real(f64),
+    Complex(Complex64),
+    Rational(BigRational),
+    Decimal(BigDecimal),
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Real(value) => write!(f, "{}", value),
+            Value::Complex(value) => write!(
+                f,
+                "{}",
+                format_complex(*value, ComplexFormat::Rectangular, AngleMode::Rad)
+            ),
+            Value::Rational(value) => write!(f, "{}", format_rational(value)),
+            Value::Decimal(value) => write!(f, "{}", format_decimal(value)),
+        }
+    }
+}
+
+pub struct FloatBackend;
+
+impl Backend for FloatBackend {
+    type Value = f64;
+
+    fn convert_real(&self, value: f64) -> Option<f64> {
+        Some(value)
+    }
+
+    fn convert_complex(&self, value: Complex64) -> Option<f64> {
+        Some(value.re).filter(|_| value.im == 0.0)
+    }
+
+    fn convert_rational(&self, value: &BigRational) -> Option<f64> {
+        ::num_traits::ToPrimitive::to_f64(value)
+    }
+
+    fn to_real(&self, value: &f64) -> Option<f64> {
+        Some(*value)
+    }
+
+    fn apply(&self, _: Operator, _: &f64, _: &f64) -> Option<f64> {
+        None
+    }
+
+    fn apply_unary(&self, _: Operator, _: &f64) -> Option<f64> {
+        None
+    }
+
+    fn call(&self, _: &str, _: &[f64], _: AngleMode) -> Option<f64> {
+        None
+    }
+
+    fn to_variable(&self, value: f64) -> VariableValue {
+        VariableValue::Constant(value)
+    }
+}
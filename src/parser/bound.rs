@@ -0,0 +1,458 @@
+use super::ast::{EvalError, ParseError};
+use super::lex::Span;
+use super::Arity;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+const MAX_ITERATIONS: usize = 200;
+const MAX_TERMS: f64 = 1E6;
+// Beyond 2^53 consecutive integers are no longer distinct in f64
+const MAX_INDEX: f64 = 9_007_199_254_740_992.0;
+const TOLERANCE: f64 = 1E-15;
+const INTEGRAL_TOLERANCE: f64 = 1E-10;
+const EXPANSIONS: i32 = 64;
+const SUBDIVISIONS: usize = 1000;
+
+const KRONROD_NODES: [f64; 8] = [
+    0.991_455_371_120_812_6,
+    0.949_107_912_342_758_5,
+    0.864_864_423_359_769_1,
+    0.741_531_185_599_394_5,
+    0.586_087_235_467_691_1,
+    0.405_845_151_377_397_2,
+    0.207_784_955_007_898_48,
+    0.0,
+];
+const KRONROD_WEIGHTS: [f64; 8] = [
+    0.022_935_322_010_529_224,
+    0.063_092_092_629_978_56,
+    0.104_790_010_322_250_19,
+    0.140_653_259_715_525_92,
+    0.169_004_726_639_267_9,
+    0.190_350_578_064_785_42,
+    0.204_432_940_075_298_89,
+    0.209_482_141_084_727_82,
+];
+const GAUSS_WEIGHTS: [f64; 4] = [
+    0.129_484_966_168_869_7,
+    0.279_705_391_489_276_64,
+    0.381_830_050_505_118_9,
+    0.417_959_183_673_469_4,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BoundFunc {
+    Solve,
+    Integrate,
+    Sum,
+    Prod,
+}
+
+impl BoundFunc {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "solve" => Some(BoundFunc::Solve),
+            "integrate" => Some(BoundFunc::Integrate),
+            "sum" => Some(BoundFunc::Sum),
+            "prod" => Some(BoundFunc::Prod),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BoundFunc::Solve => "solve",
+            BoundFunc::Integrate => "integrate",
+            BoundFunc::Sum => "sum",
+            BoundFunc::Prod => "prod",
+        }
+    }
+
+    pub fn arity(&self) -> Arity {
+        match self {
+            BoundFunc::Solve => Arity::Range(2, 4),
+            BoundFunc::Integrate | BoundFunc::Sum | BoundFunc::Prod => Arity::Fixed(4),
+        }
+    }
+
+    pub fn apply<F>(&self, f: F, args: &[f64], body: Span, span: Span) -> Result<f64, ParseError>
+    where
+        F: FnMut(f64) -> Result<f64, ParseError>,
+    {
+        match (self, args) {
+            (BoundFunc::Solve, &[]) => Solver { f, span }.near(0.0),
+            (BoundFunc::Solve, &[guess]) => Solver { f, span }.near(guess),
+            (BoundFunc::Solve, &[a, b, ..]) => Solver { f, span }.within(a, b),
+            (BoundFunc::Integrate, &[a, b, ..]) => integrate(f, a, b, span).map(|(value, _)| value),
+            (_, &[from, to, ..]) => self.series(f, from, to, body, span),
+            _ => Err(ParseError::ArgumentCount {
+                func: self.name().to_string(),
+                expected: self.arity(),
+                found: args.len() + 2,
+                span,
+            }),
+        }
+    }
+
+    fn series<F>(
+        &self,
+        mut f: F,
+        from: f64,
+        to: f64,
+        body: Span,
+        span: Span,
+    ) -> Result<f64, ParseError>
+    where
+        F: FnMut(f64) -> Result<f64, ParseError>,
+    {
+        let integer = |value: f64| {
+            Some(value)
+                .filter(|value| value.fract() == 0.0 && value.abs() <= MAX_INDEX)
+                .ok_or_else(|| {
+                    ParseError::Eval(EvalError::Domain(self.name().to_string(), value), span)
+                })
+        };
+        let (from, to) = (integer(from)?, integer(to)?);
+        if to - from >= MAX_TERMS {
+            return Err(ParseError::IterationLimit(self.name().to_string(), span));
+        }
+
+        let mut total: f64 = if *self == BoundFunc::Prod { 1.0 } else { 0.0 };
+        let terms = if to >= from {
+            (to - from) as u64 + 1
+        } else {
+            0
+        };
+        for i in 0..terms {
+            let term = f(from + i as f64)?;
+            if *self == BoundFunc::Prod {
+                total *= term;
+            } else {
+                total += term;
+            }
+            if !total.is_finite() {
+                return Err(ParseError::Eval(EvalError::Overflow, body));
+            }
+        }
+        Ok(total)
+    }
+}
+
+pub fn integrate<F>(mut f: F, a: f64, b: f64, span: Span) -> Result<(f64, f64), ParseError>
+where
+    F: FnMut(f64) -> Result<f64, ParseError>,
+{
+    let name = BoundFunc::Integrate.name();
+    let domain = |bound: f64| ParseError::Eval(EvalError::Domain(name.to_string(), bound), span);
+    if !a.is_finite() {
+        return Err(domain(a));
+    }
+    if !b.is_finite() || !(b - a).is_finite() {
+        return Err(domain(b));
+    }
+    let mut first = Interval::new(&mut f, a, b)?;
+    let mut value = first.value;
+    let mut error = first.error;
+    let mut intervals = BinaryHeap::new();
+    for _ in 0..MAX_ITERATIONS {
+        if error <= INTEGRAL_TOLERANCE.max(INTEGRAL_TOLERANCE * value.abs()) {
+            return Ok((value, error));
+        }
+        let mid = (first.a + first.b) / 2.0;
+        let left = Interval::new(&mut f, first.a, mid)?;
+        let right = Interval::new(&mut f, mid, first.b)?;
+        value += left.value + right.value - first.value;
+        error += left.error + right.error - first.error;
+        intervals.push(left);
+        intervals.push(right);
+        first = match intervals.pop() {
+            Some(interval) => interval,
+            None => break,
+        };
+    }
+    Err(ParseError::NoConvergence(name.to_string(), span))
+}
+
+struct Interval {
+    a: f64,
+    b: f64,
+    value: f64,
+    error: f64,
+}
+
+impl Interval {
+    fn new<F>(f: &mut F, a: f64, b: f64) -> Result<Self, ParseError>
+    where
+        F: FnMut(f64) -> Result<f64, ParseError>,
+    {
+        let center = (a + b) / 2.0;
+        let half = (b - a) / 2.0;
+        let mut kronrod = 0.0;
+        let mut gauss = 0.0;
+        for (i, (node, weight)) in KRONROD_NODES.iter().zip(&KRONROD_WEIGHTS).enumerate() {
+            let values = if *node == 0.0 {
+                f(center)?
+            } else {
+                f(center - half * node)? + f(center + half * node)?
+            };
+            kronrod += weight * values;
+            if i % 2 == 1 {
+                gauss += GAUSS_WEIGHTS[i / 2] * values;
+            }
+        }
+        Ok(Self {
+            a,
+            b,
+            value: kronrod * half,
+            error: ((kronrod - gauss) * half).abs(),
+        })
+    }
+}
+
+impl PartialEq for Interval {
+    fn eq(&self, other: &Self) -> bool {
+        self.error == other.error
+    }
+}
+
+impl Eq for Interval {}
+
+impl PartialOrd for Interval {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Interval {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.error.total_cmp(&other.error)
+    }
+}
+
+pub fn roots<F>(f: F, a: f64, b: f64, span: Span) -> Result<Vec<f64>, ParseError>
+where
+    F: FnMut(f64) -> Result<f64, ParseError>,
+{
+    Solver { f, span }.scan(a.min(b), a.max(b), true)
+}
+
+struct Solver<F> {
+    f: F,
+    span: Span,
+}
+
+impl<F> Solver<F>
+where
+    F: FnMut(f64) -> Result<f64, ParseError>,
+{
+    fn error(&self) -> ParseError {
+        ParseError::NoConvergence(BoundFunc::Solve.name().to_string(), self.span)
+    }
+
+    fn sample(&mut self, x: f64) -> Result<Option<f64>, ParseError> {
+        if !x.is_finite() {
+            return Ok(None);
+        }
+        match (self.f)(x) {
+            Ok(value) => Ok(Some(value)),
+            Err(ParseError::Eval(..)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn near(&mut self, guess: f64) -> Result<f64, ParseError> {
+        let start = self.sample(guess)?;
+        if start == Some(0.0) {
+            return Ok(guess);
+        }
+
+        let scale = guess.abs().max(1.0);
+        let mut sides = [(guess, start); 2];
+        for k in 0..EXPANSIONS {
+            let step = scale * 0.01 * 2f64.powi(k);
+            for (side, direction) in [1.0, -1.0].iter().enumerate() {
+                let x = guess + direction * step;
+                let fx = self.sample(x)?;
+                if fx == Some(0.0) {
+                    return Ok(x);
+                }
+                if let ((prev, Some(fp)), Some(fx)) = (sides[side], fx) {
+                    if (fp < 0.0) != (fx < 0.0) {
+                        if let Some(root) = self.bracketed(prev, fp, x, fx)? {
+                            return Ok(root);
+                        }
+                    }
+                }
+                sides[side] = (x, fx);
+            }
+        }
+        self.secant(guess)
+    }
+
+    fn within(&mut self, a: f64, b: f64) -> Result<f64, ParseError> {
+        let (a, b) = (a.min(b), a.max(b));
+        if let Some(root) = self.scan(a, b, false)?.first() {
+            return Ok(*root);
+        }
+        match self.secant((a + b) / 2.0) {
+            Ok(root) if a <= root && root <= b => Ok(root),
+            _ => Err(self.error()),
+        }
+    }
+
+    fn scan(&mut self, a: f64, b: f64, all: bool) -> Result<Vec<f64>, ParseError> {
+        let mut roots: Vec<f64> = vec![];
+        let mut prev = (a, None);
+        for i in 0..=SUBDIVISIONS {
+            let x = if i == SUBDIVISIONS {
+                b
+            } else {
+                a + (b - a) * i as f64 / SUBDIVISIONS as f64
+            };
+            let fx = self.sample(x)?;
+            let root = match (prev, fx) {
+                (_, Some(0.0)) => Some(x),
+                ((prev, Some(fp)), Some(fx)) if fp != 0.0 && (fp < 0.0) != (fx < 0.0) => {
+                    self.bracketed(prev, fp, x, fx)?
+                }
+                _ => None,
+            };
+            if let Some(root) = root {
+                let tolerance = 4.0 * f64::EPSILON * root.abs().max(1.0);
+                if !roots
+                    .last()
+                    .is_some_and(|last| (root - last).abs() <= tolerance)
+                {
+                    roots.push(root);
+                }
+                if !all {
+                    break;
+                }
+            }
+            prev = (x, fx);
+        }
+        Ok(roots)
+    }
+
+    fn bracketed(&mut self, a: f64, fa: f64, b: f64, fb: f64) -> Result<Option<f64>, ParseError> {
+        let root = match self.brent(a, fa, b, fb)? {
+            Some(root) => root,
+            None => return Ok(None),
+        };
+        match self.sample(root)? {
+            Some(value) if value.abs() <= fa.abs().min(fb.abs()) => Ok(Some(root)),
+            _ => Ok(None),
+        }
+    }
+
+    fn brent(
+        &mut self,
+        mut a: f64,
+        mut fa: f64,
+        mut b: f64,
+        mut fb: f64,
+    ) -> Result<Option<f64>, ParseError> {
+        let (mut c, mut fc) = (b, fb);
+        let (mut d, mut e) = (b - a, b - a);
+        for _ in 0..MAX_ITERATIONS {
+            if (fb > 0.0) == (fc > 0.0) {
+                c = a;
+                fc = fa;
+                d = b - a;
+                e = d;
+            }
+            if fc.abs() < fb.abs() {
+                a = b;
+                b = c;
+                c = a;
+                fa = fb;
+                fb = fc;
+                fc = fa;
+            }
+
+            let tolerance = 2.0 * f64::EPSILON * b.abs() + TOLERANCE;
+            let m = 0.5 * (c - b);
+            if m.abs() <= tolerance || fb == 0.0 {
+                return Ok(Some(b));
+            }
+
+            if e.abs() >= tolerance && fa.abs() > fb.abs() {
+                let s = fb / fa;
+                let (mut p, mut q) = if a == c {
+                    (2.0 * m * s, 1.0 - s)
+                } else {
+                    let q = fa / fc;
+                    let r = fb / fc;
+                    (
+                        s * (2.0 * m * q * (q - r) - (b - a) * (r - 1.0)),
+                        (q - 1.0) * (r - 1.0) * (s - 1.0),
+                    )
+                };
+                if p > 0.0 {
+                    q = -q;
+                } else {
+                    p = -p;
+                }
+                if 2.0 * p < (3.0 * m * q - (tolerance * q).abs()).min((e * q).abs()) {
+                    e = d;
+                    d = p / q;
+                } else {
+                    d = m;
+                    e = m;
+                }
+            } else {
+                d = m;
+                e = m;
+            }
+
+            a = b;
+            fa = fb;
+            b += if d.abs() > tolerance {
+                d
+            } else {
+                tolerance.copysign(m)
+            };
+            fb = match self.sample(b)? {
+                Some(value) => value,
+                None => return Ok(None),
+            };
+        }
+        Ok(None)
+    }
+
+    fn secant(&mut self, guess: f64) -> Result<f64, ParseError> {
+        let mut x0 = guess;
+        let mut x1 = guess + guess.abs().max(1.0) * 1E-4;
+        let (mut f0, mut f1) = match (self.sample(x0)?, self.sample(x1)?) {
+            (Some(f0), Some(f1)) => (f0, f1),
+            _ => return Err(self.error()),
+        };
+        let start = f0.abs();
+
+        for _ in 0..MAX_ITERATIONS {
+            if f1 == 0.0 {
+                return Ok(x1);
+            }
+            if f1 == f0 {
+                break;
+            }
+            let x2 = x1 - f1 * (x1 - x0) / (f1 - f0);
+            let f2 = match self.sample(x2)? {
+                Some(f2) => f2,
+                None => break,
+            };
+            if (x2 - x1).abs() <= 1E-12 * x2.abs().max(1.0) {
+                return if f2.abs() <= start {
+                    Ok(x2)
+                } else {
+                    Err(self.error())
+                };
+            }
+            x0 = x1;
+            f0 = f1;
+            x1 = x2;
+            f1 = f2;
+        }
+        Err(self.error())
+    }
+}
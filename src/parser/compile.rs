@@ -0,0 +1,659 @@
+use super::ast::{eval_integer, EvalError, ExprKind, Expression, ParseError, MAX_CALL_DEPTH};
+use super::bound::BoundFunc;
+use super::lex::{Operator, Span};
+use super::{
+    AngleMode, Arity, Builtin, CalcError, Environment, IntegerMode, VariableValue, DEFAULT_VARS,
+};
+use std::collections::HashMap;
+use std::thread;
+
+const MIN_ROWS_PER_THREAD: usize = 4096;
+
+type Column = Vec<f64>;
+
+#[derive(Clone, Copy)]
+enum Instr {
+    Const(f64),
+    Load(usize),
+    Unary(Operator, Span),
+    Binary(Operator, Span),
+    Percent(Operator, Span),
+    ShortCircuit(Operator, usize, Span),
+    JumpIfZero(usize),
+    Jump(usize),
+    Builtin(&'static str, &'static Builtin, usize, Span),
+    Call(usize, Span),
+    Bound(BoundFunc, usize, usize, Span),
+}
+
+struct Chunk {
+    name: String,
+    params: Vec<String>,
+    body: Expression,
+    code: Vec<Instr>,
+}
+
+pub struct Compiled {
+    main: Chunk,
+    functions: Vec<Chunk>,
+    indices: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    mode: AngleMode,
+    integer_mode: IntegerMode,
+}
+
+struct Compiler<'a> {
+    env: &'a Environment,
+    mode: AngleMode,
+    functions: Vec<Chunk>,
+    indices: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+}
+
+impl<'a> Compiler<'a> {
+    fn compile(
+        &mut self,
+        expr: &Expression,
+        params: &[String],
+        code: &mut Vec<Instr>,
+    ) -> Result<(), ParseError> {
+        use self::ExprKind::*;
+
+        let span = expr.span;
+        match &expr.kind {
+            Binary { op, left, right } => {
+                self.compile(left, params, code)?;
+                match &right.kind {
+                    Unary {
+                        op: Operator::Percent,
+                        operand,
+                    } if *op == Operator::Plus || *op == Operator::Minus => {
+                        self.compile(operand, params, code)?;
+                        code.push(Instr::Percent(*op, span));
+                    }
+                    _ if *op == Operator::And || *op == Operator::Or => {
+                        let jump = code.len();
+                        code.push(Instr::ShortCircuit(*op, 0, span));
+                        self.compile(right, params, code)?;
+                        code.push(Instr::Binary(*op, span));
+                        code[jump] = Instr::ShortCircuit(*op, code.len(), span);
+                    }
+                    _ => {
+                        self.compile(right, params, code)?;
+                        code.push(Instr::Binary(*op, span));
+                    }
+                }
+            }
+            Unary { op, operand } => {
+                self.compile(operand, params, code)?;
+                code.push(Instr::Unary(*op, span));
+            }
+            Angle { unit, operand } => {
+                self.compile(operand, params, code)?;
+                if *unit != self.mode {
+                    code.push(Instr::Const(real(unit.factor(self.mode), span)?));
+                    code.push(Instr::Binary(Operator::Mult, span));
+                }
+            }
+            Conditional {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.compile(condition, params, code)?;
+                let branch = code.len();
+                code.push(Instr::JumpIfZero(0));
+                self.compile(then, params, code)?;
+                let end = code.len();
+                code.push(Instr::Jump(0));
+                code[branch] = Instr::JumpIfZero(code.len());
+                self.compile(otherwise, params, code)?;
+                code[end] = Instr::Jump(code.len());
+            }
+            Bound {
+                func,
+                var,
+                body,
+                args,
+            } => {
+                for arg in args {
+                    self.compile(arg, params, code)?;
+                }
+                let mut scope = params.to_vec();
+                scope.push(var.clone());
+                let mut body_code = vec![];
+                self.compile(body, &scope, &mut body_code)?;
+                let index = self.functions.len();
+                self.functions.push(Chunk {
+                    name: func.name().to_string(),
+                    params: scope,
+                    body: (**body).clone(),
+                    code: body_code,
+                });
+                code.push(Instr::Bound(*func, index, args.len(), span));
+            }
+            Number(value) => code.push(Instr::Const(real(*value, span)?)),
+            Complex(value) if value.im == 0.0 => code.push(Instr::Const(real(value.re, span)?)),
+            Complex(_) => return Err(ParseError::ComplexValue(span)),
+            Rational(value) => {
+                let value = ::num_traits::ToPrimitive::to_f64(value).unwrap_or(f64::INFINITY);
+                code.push(Instr::Const(real(value, span)?));
+            }
+            Variable(name) => match params.iter().rposition(|param| param == name) {
+                Some(index) => code.push(Instr::Load(index)),
+                None => {
+                    let value = self.constant(name, span)?;
+                    self.constants.insert(name.clone(), value);
+                    code.push(Instr::Const(real(value, span)?));
+                }
+            },
+            Paren(inner) => self.compile(inner, params, code)?,
+            CallExpresion { args, func } => {
+                for arg in args {
+                    self.compile(arg, params, code)?;
+                }
+                match DEFAULT_VARS.get_key_value(func.as_str()) {
+                    Some((name, VariableValue::Function(_, f))) => {
+                        code.push(Instr::Builtin(name, f, args.len(), span))
+                    }
+                    _ => {
+                        let index = self.function(func, span)?;
+                        code.push(Instr::Call(index, span));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, name: &str, span: Span) -> Result<usize, ParseError> {
+        if let Some(index) = self.indices.get(name) {
+            return Ok(*index);
+        }
+        let (params, body) = match self.env.get(name) {
+            Some(VariableValue::UserFunction { params, body }) => (params, body),
+            _ => return Err(ParseError::NonFunction(name.to_string(), span)),
+        };
+
+        let index = self.functions.len();
+        self.functions.push(Chunk {
+            name: name.to_string(),
+            params: params.clone(),
+            body: body.clone(),
+            code: vec![],
+        });
+        self.indices.insert(name.to_string(), index);
+        let mut code = vec![];
+        self.compile(body, params, &mut code)?;
+        self.functions[index].code = code;
+        Ok(index)
+    }
+
+    fn constant(&self, name: &str, span: Span) -> Result<f64, ParseError> {
+        match self.env.get(name) {
+            Some(VariableValue::Constant(value)) => Ok(*value),
+            Some(VariableValue::Complex(value)) if value.im == 0.0 => Ok(value.re),
+            Some(VariableValue::Complex(_)) => Err(ParseError::ComplexValue(span)),
+            Some(VariableValue::Rational(value)) => {
+                Ok(::num_traits::ToPrimitive::to_f64(value).unwrap_or(f64::INFINITY))
+            }
+            _ => Err(ParseError::UndefinedIdent(name.to_string(), span)),
+        }
+    }
+}
+
+fn real(value: f64, span: Span) -> Result<f64, ParseError> {
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(ParseError::Eval(EvalError::Overflow, span))
+    }
+}
+
+fn checked(name: &str, arg: f64, value: f64, span: Span) -> Result<f64, ParseError> {
+    if value.is_nan() {
+        Err(ParseError::Eval(
+            EvalError::Domain(name.to_string(), arg),
+            span,
+        ))
+    } else {
+        real(value, span)
+    }
+}
+
+impl Compiled {
+    pub fn new(
+        expr: &Expression,
+        vars: &[String],
+        mode: AngleMode,
+        env: &Environment,
+    ) -> Result<Self, CalcError> {
+        let mut compiler = Compiler {
+            env,
+            mode,
+            functions: vec![],
+            indices: HashMap::new(),
+            constants: HashMap::new(),
+        };
+        let mut code = vec![];
+        compiler.compile(expr, vars, &mut code)?;
+        Ok(Self {
+            main: Chunk {
+                name: String::new(),
+                params: vars.to_vec(),
+                body: expr.clone(),
+                code,
+            },
+            functions: compiler.functions,
+            indices: compiler.indices,
+            constants: compiler.constants,
+            mode,
+            integer_mode: env.integer_mode(),
+        })
+    }
+
+    pub fn vars(&self) -> usize {
+        self.main.params.len()
+    }
+
+    pub fn eval(&self, values: &[f64]) -> Result<f64, CalcError> {
+        if values.len() != self.vars() {
+            return Err(CalcError::ArgumentCount {
+                func: "eval".to_string(),
+                expected: Arity::Fixed(self.vars()),
+                found: values.len(),
+                span: self.main.body.span,
+            });
+        }
+        let mut stack = values.to_vec();
+        self.run(&self.main, 0, &mut stack, 0)?;
+        Ok(stack.pop().unwrap_or_default())
+    }
+
+    pub fn eval_columns(&self, columns: &[&[f64]]) -> Vec<Result<f64, CalcError>> {
+        let rows = rows(columns);
+        if !self.is_aligned(columns, rows) {
+            // Rows with missing values fail the same way `eval` does
+            return (0..rows)
+                .map(|row| {
+                    let values: Vec<_> = columns
+                        .iter()
+                        .filter_map(|column| column.get(row))
+                        .cloned()
+                        .collect();
+                    self.eval(&values)
+                })
+                .collect();
+        }
+        let frame: Vec<Column> = columns.iter().map(|column| column.to_vec()).collect();
+        let mut errors: Vec<_> = (0..rows).map(|_| None).collect();
+        let values = self.column(
+            &self.main.params,
+            &self.main.body,
+            &frame,
+            &vec![true; rows],
+            &mut errors,
+            0,
+        );
+        values
+            .into_iter()
+            .zip(errors)
+            .map(|(value, error)| match error {
+                Some(e) => Err(e.into()),
+                None => Ok(value),
+            })
+            .collect()
+    }
+
+    pub fn eval_columns_parallel(
+        &self,
+        columns: &[&[f64]],
+        threads: usize,
+    ) -> Vec<Result<f64, CalcError>> {
+        let rows = rows(columns);
+        let threads = threads.min(rows / MIN_ROWS_PER_THREAD);
+        if threads <= 1 || !self.is_aligned(columns, rows) {
+            return self.eval_columns(columns);
+        }
+
+        let size = rows.div_ceil(threads);
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..rows)
+                .step_by(size)
+                .map(|start| {
+                    let end = rows.min(start + size);
+                    let columns: Vec<&[f64]> =
+                        columns.iter().map(|column| &column[start..end]).collect();
+                    scope.spawn(move || self.eval_columns(&columns))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("column evaluation panicked"))
+                .collect()
+        })
+    }
+
+    fn is_aligned(&self, columns: &[&[f64]], rows: usize) -> bool {
+        columns.len() == self.vars() && columns.iter().all(|column| column.len() == rows)
+    }
+
+    fn run(
+        &self,
+        chunk: &Chunk,
+        base: usize,
+        stack: &mut Vec<f64>,
+        depth: usize,
+    ) -> Result<(), ParseError> {
+        let mut pc = 0;
+        while let Some(instr) = chunk.code.get(pc) {
+            pc += 1;
+            match *instr {
+                Instr::Const(value) => stack.push(value),
+                Instr::Load(index) => stack.push(stack[base + index]),
+                Instr::Unary(op, span) => {
+                    let value = pop(stack);
+                    stack.push(self.unary(op, value, span)?);
+                }
+                Instr::Binary(op, span) => {
+                    let right = pop(stack);
+                    let left = pop(stack);
+                    stack.push(self.binary(op, left, right, span)?);
+                }
+                Instr::Percent(op, span) => {
+                    let percent = self.unary(Operator::Percent, pop(stack), span)?;
+                    let left = pop(stack);
+                    let right = self.binary(Operator::Mult, left, percent, span)?;
+                    stack.push(self.binary(op, left, right, span)?);
+                }
+                Instr::ShortCircuit(op, target, span) => {
+                    let left = stack.last().cloned().unwrap_or_default();
+                    if let Some(value) = op.short_circuit(left) {
+                        pop(stack);
+                        stack.push(real(value, span)?);
+                        pc = target;
+                    }
+                }
+                Instr::JumpIfZero(target) => {
+                    if pop(stack) == 0.0 {
+                        pc = target;
+                    }
+                }
+                Instr::Jump(target) => pc = target,
+                Instr::Builtin(name, f, count, span) => {
+                    let start = stack.len() - count;
+                    let args = &stack[start..];
+                    let value = f(args, self.mode).map_err(|e| ParseError::Eval(e, span))?;
+                    let value = checked(name, args.first().cloned().unwrap_or(value), value, span)?;
+                    stack.truncate(start);
+                    stack.push(value);
+                }
+                Instr::Bound(func, index, count, span) => {
+                    let start = stack.len() - count;
+                    let locals = &stack[base..base + chunk.params.len()];
+                    let value = self.bound(func, index, locals, &stack[start..], depth, span)?;
+                    stack.truncate(start);
+                    stack.push(value);
+                }
+                Instr::Call(index, span) => {
+                    let function = &self.functions[index];
+                    if depth >= MAX_CALL_DEPTH {
+                        return Err(ParseError::RecursionLimit(function.name.clone(), span));
+                    }
+                    let start = stack.len() - function.params.len();
+                    self.run(function, start, stack, depth + 1)?;
+                    let value = pop(stack);
+                    stack.truncate(start);
+                    stack.push(value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn column(
+        &self,
+        params: &[String],
+        expr: &Expression,
+        frame: &[Column],
+        mask: &[bool],
+        errors: &mut [Option<ParseError>],
+        depth: usize,
+    ) -> Column {
+        use self::ExprKind::*;
+
+        if !is_live(mask, errors) {
+            return vec![0.0; mask.len()];
+        }
+        let span = expr.span;
+        let column = |expr: &Expression, mask: &[bool], errors: &mut [Option<ParseError>]| {
+            self.column(params, expr, frame, mask, errors, depth)
+        };
+        match &expr.kind {
+            Binary { op, left, right } => {
+                let left = column(left, mask, errors);
+                match &right.kind {
+                    Unary {
+                        op: Operator::Percent,
+                        operand,
+                    } if *op == Operator::Plus || *op == Operator::Minus => {
+                        let operand = column(operand, mask, errors);
+                        each(mask, errors, |row| {
+                            let percent = self.unary(Operator::Percent, operand[row], span)?;
+                            let right = self.binary(Operator::Mult, left[row], percent, span)?;
+                            self.binary(*op, left[row], right, span)
+                        })
+                    }
+                    _ if *op == Operator::And || *op == Operator::Or => {
+                        let rest: Vec<bool> = mask
+                            .iter()
+                            .zip(&left)
+                            .map(|(&live, &left)| live && op.short_circuit(left).is_none())
+                            .collect();
+                        let right = column(right, &rest, errors);
+                        each(mask, errors, |row| match op.short_circuit(left[row]) {
+                            Some(value) => real(value, span),
+                            None => self.binary(*op, left[row], right[row], span),
+                        })
+                    }
+                    _ => {
+                        let right = column(right, mask, errors);
+                        each(mask, errors, |row| {
+                            self.binary(*op, left[row], right[row], span)
+                        })
+                    }
+                }
+            }
+            Unary { op, operand } => {
+                let operand = column(operand, mask, errors);
+                each(mask, errors, |row| self.unary(*op, operand[row], span))
+            }
+            Angle { unit, operand } if *unit == self.mode => column(operand, mask, errors),
+            Angle { unit, operand } => {
+                let operand = column(operand, mask, errors);
+                let factor = unit.factor(self.mode);
+                each(mask, errors, |row| {
+                    self.binary(Operator::Mult, operand[row], factor, span)
+                })
+            }
+            Conditional {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let condition = column(condition, mask, errors);
+                let taken: Vec<bool> = condition.iter().map(|&value| value != 0.0).collect();
+                let branch = |taken: bool| -> Vec<bool> {
+                    mask.iter()
+                        .zip(&condition)
+                        .map(|(&live, &value)| live && (value != 0.0) == taken)
+                        .collect()
+                };
+                let then = column(then, &branch(true), errors);
+                let otherwise = column(otherwise, &branch(false), errors);
+                (0..mask.len())
+                    .map(|row| {
+                        if taken[row] {
+                            then[row]
+                        } else {
+                            otherwise[row]
+                        }
+                    })
+                    .collect()
+            }
+            Number(value) => each(mask, errors, |_| real(*value, span)),
+            Complex(value) if value.im == 0.0 => each(mask, errors, |_| real(value.re, span)),
+            Complex(_) => each(mask, errors, |_| Err(ParseError::ComplexValue(span))),
+            Rational(value) => {
+                let value = ::num_traits::ToPrimitive::to_f64(value).unwrap_or(f64::INFINITY);
+                each(mask, errors, |_| real(value, span))
+            }
+            Variable(name) => match params.iter().rposition(|param| param == name) {
+                Some(index) => frame[index].clone(),
+                None => each(mask, errors, |_| match self.constants.get(name) {
+                    Some(&value) => real(value, span),
+                    None => Err(ParseError::UndefinedIdent(name.clone(), span)),
+                }),
+            },
+            Paren(inner) => column(inner, mask, errors),
+            Bound {
+                func,
+                var,
+                body,
+                args,
+            } => {
+                let args: Vec<Column> = args.iter().map(|arg| column(arg, mask, errors)).collect();
+                let mut scope = params.to_vec();
+                scope.push(var.clone());
+                let mut values = vec![];
+                each(mask, errors, |row| {
+                    values.clear();
+                    values.extend(args.iter().map(|arg| arg[row]));
+                    let mut locals: Vec<Column> =
+                        frame.iter().map(|column| vec![column[row]]).collect();
+                    let f = |x: f64| {
+                        locals.truncate(frame.len());
+                        locals.push(vec![real(x, span)?]);
+                        let mut error = [None];
+                        let value = self.column(&scope, body, &locals, &[true], &mut error, depth);
+                        match error[0].take() {
+                            Some(e) => Err(e),
+                            None => Ok(value[0]),
+                        }
+                    };
+                    func.apply(f, &values, body.span, span)
+                })
+            }
+            CallExpresion { args, func } => {
+                let args: Vec<Column> = args.iter().map(|arg| column(arg, mask, errors)).collect();
+                match DEFAULT_VARS.get_key_value(func.as_str()) {
+                    Some((name, VariableValue::Function(_, f))) => {
+                        let mut values = Vec::with_capacity(args.len());
+                        each(mask, errors, |row| {
+                            values.clear();
+                            values.extend(args.iter().map(|arg| arg[row]));
+                            let value =
+                                f(&values, self.mode).map_err(|e| ParseError::Eval(e, span))?;
+                            checked(name, values.first().cloned().unwrap_or(value), value, span)
+                        })
+                    }
+                    _ => {
+                        let function = &self.functions[self.indices[func]];
+                        if depth >= MAX_CALL_DEPTH {
+                            return each(mask, errors, |_| {
+                                Err(ParseError::RecursionLimit(function.name.clone(), span))
+                            });
+                        }
+                        self.column(
+                            &function.params,
+                            &function.body,
+                            &args,
+                            mask,
+                            errors,
+                            depth + 1,
+                        )
+                    }
+                }
+            }
+        }
+    }
+
+    fn bound(
+        &self,
+        func: BoundFunc,
+        index: usize,
+        locals: &[f64],
+        args: &[f64],
+        depth: usize,
+        span: Span,
+    ) -> Result<f64, ParseError> {
+        let function = &self.functions[index];
+        let mut stack = Vec::with_capacity(function.params.len());
+        let f = |x: f64| {
+            stack.clear();
+            stack.extend_from_slice(locals);
+            stack.push(real(x, span)?);
+            self.run(function, 0, &mut stack, depth)?;
+            Ok(pop(&mut stack))
+        };
+        func.apply(f, args, function.body.span, span)
+    }
+
+    fn unary(&self, op: Operator, value: f64, span: Span) -> Result<f64, ParseError> {
+        if op.is_bitwise() {
+            return real(eval_integer(op, value, 0.0, self.integer_mode, span)?, span);
+        }
+        let result = op.apply_unary(value).ok_or_else(|| {
+            ParseError::Eval(EvalError::Domain(op.symbol().to_string(), value), span)
+        })?;
+        checked(op.symbol(), value, result, span)
+    }
+
+    fn binary(&self, op: Operator, left: f64, right: f64, span: Span) -> Result<f64, ParseError> {
+        if op.divides_by_zero(Some(left), Some(right)) {
+            return Err(ParseError::Eval(EvalError::DivisionByZero, span));
+        }
+        if op.is_bitwise() {
+            return real(
+                eval_integer(op, left, right, self.integer_mode, span)?,
+                span,
+            );
+        }
+        let value = op.apply(left, right);
+        if value == 0.0 && left != 0.0 && right != 0.0 && op.can_underflow() {
+            return Err(ParseError::Eval(EvalError::Underflow, span));
+        }
+        checked(op.symbol(), left, value, span)
+    }
+}
+
+fn pop(stack: &mut Vec<f64>) -> f64 {
+    stack.pop().unwrap_or_default()
+}
+
+fn rows(columns: &[&[f64]]) -> usize {
+    columns.iter().map(|column| column.len()).max().unwrap_or(1)
+}
+
+fn is_live(mask: &[bool], errors: &[Option<ParseError>]) -> bool {
+    mask.iter()
+        .zip(errors)
+        .any(|(&live, error)| live && error.is_none())
+}
+
+fn each<F>(mask: &[bool], errors: &mut [Option<ParseError>], mut f: F) -> Column
+where
+    F: FnMut(usize) -> Result<f64, ParseError>,
+{
+    let mut column = vec![0.0; mask.len()];
+    for (row, value) in column.iter_mut().enumerate() {
+        if !mask[row] || errors[row].is_some() {
+            continue;
+        }
+        match f(row) {
+            Ok(result) => *value = result,
+            Err(e) => errors[row] = Some(e),
+        }
+    }
+    column
+}
@@ -0,0 +1,251 @@
+use super::backend::Backend;
+use super::lex::Operator;
+use super::{
+    to_fixed, AngleMode, VariableValue, BERNOULLI, DEFAULT_VARS, LANCZOS_COEFFICIENTS, LANCZOS_G,
+    POLYGAMMA_SHIFT,
+};
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+pub const IMAGINARY: &str = "i";
+
+pub static IMAGINARY_UNIT: VariableValue = VariableValue::Complex(Complex64::new(0.0, 1.0));
+
+type ComplexFunction = Box<dyn Fn(&[Complex64], AngleMode) -> Option<Complex64> + Send + Sync>;
+
+lazy_static! {
+    static ref COMPLEX_FUNCTIONS: HashMap<&'static str, ComplexFunction> = complex_map! {
+        sin(mode) { &[z] => to_radians(z, mode).sin() },
+        cos(mode) { &[z] => to_radians(z, mode).cos() },
+        tan(mode) { &[z] => to_radians(z, mode).tan() },
+        asin(mode) { &[z] => from_radians(z.asin(), mode) },
+        acos(mode) { &[z] => from_radians(z.acos(), mode) },
+        atan(mode) { &[z] => from_radians(z.atan(), mode) },
+        atan2(mode) { &[y, x] => from_radians(atan2(y, x), mode) },
+        hypot(_mode) { &[z, w] => Complex64::from((z.norm_sqr() + w.norm_sqr()).sqrt()) },
+        nCr(_mode) { &[n, r] => gamma(n + 1.0) / (gamma(r + 1.0) * gamma(n - r + 1.0)) },
+        nPr(_mode) { &[n, r] => gamma(n + 1.0) / gamma(n - r + 1.0) },
+        root(_mode) { &[z, n] => z.powc(n.inv()) },
+        ceil(_mode) { &[z] => Complex64::new(z.re.ceil(), z.im.ceil()) },
+        floor(_mode) { &[z] => Complex64::new(z.re.floor(), z.im.floor()) },
+        round(_mode) { &[z] => Complex64::new(z.re.round(), z.im.round()) },
+        ln(_mode) { &[z] => z.ln() },
+        log(_mode) {
+            &[z] => z.log10(),
+            &[z, base] => z.ln() / base.ln()
+        },
+        abs(_mode) { &[z] => Complex64::from(z.norm()) },
+        sqrt(_mode) { &[z] => z.sqrt() },
+        gamma(_mode) { &[z] => gamma(z) },
+        polygamma(_mode) { &[n, z] if is_order(n) => polygamma(n.re as u32, z) }
+    };
+}
+
+fn to_radians(z: Complex64, mode: AngleMode) -> Complex64 {
+    match mode {
+        AngleMode::Deg => z * PI / 180.0,
+        AngleMode::Rad => z,
+        AngleMode::Grad => z * PI / 200.0,
+    }
+}
+
+fn from_radians(z: Complex64, mode: AngleMode) -> Complex64 {
+    match mode {
+        AngleMode::Deg => z * 180.0 / PI,
+        AngleMode::Rad => z,
+        AngleMode::Grad => z * 200.0 / PI,
+    }
+}
+
+fn gamma(z: Complex64) -> Complex64 {
+    if z.re < 0.5 {
+        PI / ((z * PI).sin() * gamma(1.0 - z))
+    } else {
+        let z = z - 1.0;
+        let t = z + LANCZOS_G + 0.5;
+        let sum = LANCZOS_COEFFICIENTS[1..]
+            .iter()
+            .enumerate()
+            .fold(Complex64::from(LANCZOS_COEFFICIENTS[0]), |acc, (i, c)| {
+                acc + c / (z + i as f64 + 1.0)
+            });
+        (2.0 * PI).sqrt() * sum * t.powc(z + 0.5) * (-t).exp()
+    }
+}
+
+fn atan2(y: Complex64, x: Complex64) -> Complex64 {
+    let i = Complex64::i();
+    -i * ((x + i * y) / (x * x + y * y).sqrt()).ln()
+}
+
+fn is_order(n: Complex64) -> bool {
+    n.im == 0.0 && n.re.fract() == 0.0 && n.re >= 0.0
+}
+
+fn polygamma(n: u32, z: Complex64) -> Complex64 {
+    if z.re < -1E6 {
+        return Complex64::new(f64::NAN, f64::NAN);
+    }
+
+    let n_f = n as f64;
+    let sign = (-1f64).powi(n as i32 + 1);
+    let mut z = z;
+    let mut shift = Complex64::from(0.0);
+    while z.re < POLYGAMMA_SHIFT {
+        shift += z.powi(-(n as i32 + 1));
+        z += 1.0;
+    }
+
+    let series = BERNOULLI
+        .iter()
+        .enumerate()
+        .fold(Complex64::from(0.0), |acc, (k, b)| {
+            let k = 2.0 * (k as f64 + 1.0);
+            if n == 0 {
+                acc + b / (k * z.powf(k))
+            } else {
+                acc + b * super::gamma(k + n_f) / (super::gamma(k + 1.0) * z.powf(k + n_f))
+            }
+        });
+    let value = if n == 0 {
+        z.ln() - 0.5 / z - series
+    } else {
+        let (gamma_n, gamma_n1) = (super::gamma(n_f), super::gamma(n_f + 1.0));
+        sign * (gamma_n / z.powf(n_f) + gamma_n1 / (2.0 * z.powf(n_f + 1.0)) + series)
+    };
+    value + sign * super::gamma(n_f + 1.0) * shift
+}
+
+pub struct ComplexBackend;
+
+impl ComplexBackend {
+    fn is_real(values: &[Complex64]) -> bool {
+        values.iter().all(|value| value.im == 0.0)
+    }
+}
+
+impl Backend for ComplexBackend {
+    type Value = Complex64;
+
+    fn convert_real(&self, value: f64) -> Option<Complex64> {
+        Some(Complex64::from(value))
+    }
+
+    fn convert_complex(&self, value: Complex64) -> Option<Complex64> {
+        Some(value)
+    }
+
+    fn convert_rational(&self, value: &BigRational) -> Option<Complex64> {
+        value.to_f64().map(Complex64::from)
+    }
+
+    fn to_real(&self, value: &Complex64) -> Option<f64> {
+        Some(value.re).filter(|_| value.im == 0.0)
+    }
+
+    fn is_finite(&self, value: &Complex64) -> bool {
+        value.is_finite()
+    }
+
+    fn apply(&self, op: Operator, left: &Complex64, right: &Complex64) -> Option<Complex64> {
+        let (left, right) = (*left, *right);
+        if Self::is_real(&[left, right]) && !op.apply(left.re, right.re).is_nan() {
+            return None;
+        }
+        match op {
+            Operator::Plus => Some(left + right),
+            Operator::Minus => Some(left - right),
+            Operator::Mult => Some(left * right),
+            Operator::Div => Some(left / right),
+            Operator::Exp => Some(left.powc(right)),
+            Operator::Equal => Some(Complex64::from(if left == right { 1.0 } else { 0.0 })),
+            Operator::NotEqual => Some(Complex64::from(if left != right { 1.0 } else { 0.0 })),
+            _ => None,
+        }
+    }
+
+    fn apply_unary(&self, op: Operator, value: &Complex64) -> Option<Complex64> {
+        if Self::is_real(&[*value]) {
+            return None;
+        }
+        match op {
+            Operator::Neg => Some(-value),
+            Operator::Percent => Some(value / 100.0),
+            Operator::Fact => Some(gamma(value + 1.0)),
+            _ => None,
+        }
+    }
+
+    fn call(&self, func: &str, args: &[Complex64], mode: AngleMode) -> Option<Complex64> {
+        if Self::is_real(args) {
+            let real = args.iter().map(|arg| arg.re).collect::<Vec<_>>();
+            if let Some(VariableValue::Function(_, f)) = DEFAULT_VARS.get(func) {
+                if f(&real, mode).is_ok_and(|value| !value.is_nan()) {
+                    return None;
+                }
+            }
+        }
+        // Real arguments with no finite result fall back to the real builtin's error
+        COMPLEX_FUNCTIONS
+            .get(func)
+            .and_then(|f| f(args, mode))
+            .filter(|value| value.is_finite() || !Self::is_real(args))
+    }
+
+    fn to_variable(&self, value: Complex64) -> VariableValue {
+        match self.to_real(&value) {
+            Some(value) => VariableValue::Constant(value),
+            None => VariableValue::Complex(value),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ComplexFormat {
+    Rectangular,
+    Polar,
+}
+
+fn format_part(value: f64) -> String {
+    let value = to_fixed(value, 7);
+    if value == 0.0 {
+        "0".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn format_complex(value: Complex64, format: ComplexFormat, mode: AngleMode) -> String {
+    match format {
+        ComplexFormat::Rectangular => {
+            let re = format_part(value.re);
+            let im = match format_part(value.im.abs()) {
+                ref im if im == "1" => String::new(),
+                im => im,
+            };
+            let negative = value.im < 0.0;
+            if to_fixed(value.im, 7) == 0.0 {
+                re
+            } else if re == "0" {
+                format!("{}{}i", if negative { "-" } else { "" }, im)
+            } else {
+                format!("{} {} {}i", re, if negative { "-" } else { "+" }, im)
+            }
+        }
+        ComplexFormat::Polar => {
+            let (r, theta) = value.to_polar();
+            match mode {
+                AngleMode::Rad => format!("{}∠{}", format_part(r), format_part(theta)),
+                _ => format!(
+                    "{}∠{}{}",
+                    format_part(r),
+                    format_part(mode.from_radians(theta)),
+                    mode.suffix()
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,494 @@
+use super::ast::{ExprKind, Expression, ParseError, MAX_CALL_DEPTH};
+use super::bound::BoundFunc;
+use super::lex::{Operator, Span};
+use super::{AngleMode, Environment, VariableValue};
+use std::f64::consts::LN_10;
+
+fn number(value: f64, span: Span) -> Expression {
+    Expression::new(ExprKind::Number(value), span)
+}
+
+fn is_number(expr: &Expression, value: f64) -> bool {
+    matches!(expr.kind, ExprKind::Number(n) if n == value)
+}
+
+fn binary(op: Operator, left: Expression, right: Expression) -> Expression {
+    let span = left.span.to(right.span);
+    Expression::new(
+        ExprKind::Binary {
+            op,
+            left: left.into(),
+            right: right.into(),
+        },
+        span,
+    )
+}
+
+fn add(left: Expression, right: Expression) -> Expression {
+    if is_number(&left, 0.0) {
+        right
+    } else if is_number(&right, 0.0) {
+        left
+    } else {
+        binary(Operator::Plus, left, right)
+    }
+}
+
+fn sub(left: Expression, right: Expression) -> Expression {
+    if is_number(&right, 0.0) {
+        left
+    } else if is_number(&left, 0.0) {
+        neg(right)
+    } else {
+        binary(Operator::Minus, left, right)
+    }
+}
+
+fn mul(left: Expression, right: Expression) -> Expression {
+    if is_number(&left, 0.0) || is_number(&right, 0.0) {
+        number(0.0, left.span.to(right.span))
+    } else if is_number(&left, 1.0) {
+        right
+    } else if is_number(&right, 1.0) {
+        left
+    } else {
+        binary(Operator::Mult, left, right)
+    }
+}
+
+fn div(left: Expression, right: Expression) -> Expression {
+    if is_number(&left, 0.0) || is_number(&right, 1.0) {
+        left
+    } else {
+        binary(Operator::Div, left, right)
+    }
+}
+
+fn pow(left: Expression, right: Expression) -> Expression {
+    binary(Operator::Exp, left, right)
+}
+
+fn neg(operand: Expression) -> Expression {
+    if is_number(&operand, 0.0) {
+        return operand;
+    }
+    let span = operand.span;
+    Expression::new(
+        ExprKind::Unary {
+            op: Operator::Neg,
+            operand: operand.into(),
+        },
+        span,
+    )
+}
+
+fn call(func: &str, args: Vec<Expression>, span: Span) -> Expression {
+    Expression::new(
+        ExprKind::CallExpresion {
+            func: func.to_string(),
+            args,
+        },
+        span,
+    )
+}
+
+impl Expression {
+    pub fn depends_on(&self, var: &str) -> bool {
+        use self::ExprKind::*;
+
+        match &self.kind {
+            Binary { left, right, .. } => left.depends_on(var) || right.depends_on(var),
+            Unary { operand, .. } | Angle { operand, .. } => operand.depends_on(var),
+            Conditional {
+                condition,
+                then,
+                otherwise,
+            } => condition.depends_on(var) || then.depends_on(var) || otherwise.depends_on(var),
+            CallExpresion { args, .. } => args.iter().any(|arg| arg.depends_on(var)),
+            Bound {
+                var: bound,
+                body,
+                args,
+                ..
+            } => {
+                (bound != var && body.depends_on(var)) || args.iter().any(|arg| arg.depends_on(var))
+            }
+            Variable(name) => name == var,
+            Paren(inner) => inner.depends_on(var),
+            Number(_) | Complex(_) | Rational(_) => false,
+        }
+    }
+
+    fn substitute(&self, params: &[String], args: &[Expression]) -> Expression {
+        self.replace(&|name| {
+            params
+                .iter()
+                .position(|param| param == name)
+                .map(|index| ExprKind::Paren(args[index].clone().into()))
+        })
+    }
+
+    // Globals are fixed before inlining so they can't capture the differentiation variable
+    fn resolve_globals(&self, params: &[String], context: &Environment) -> Expression {
+        self.replace(&|name| {
+            if params.iter().any(|param| param == name) {
+                return None;
+            }
+            match context.get(name) {
+                Some(VariableValue::Constant(value)) => Some(ExprKind::Number(*value)),
+                Some(VariableValue::Complex(value)) => Some(ExprKind::Complex(*value)),
+                Some(VariableValue::Rational(value)) => Some(ExprKind::Rational(value.clone())),
+                _ => None,
+            }
+        })
+    }
+
+    fn replace(&self, f: &dyn Fn(&str) -> Option<ExprKind>) -> Expression {
+        use self::ExprKind::*;
+
+        let sub = |expr: &Expression| Box::new(expr.replace(f));
+        let kind = match &self.kind {
+            Binary { op, left, right } => Binary {
+                op: *op,
+                left: sub(left),
+                right: sub(right),
+            },
+            Unary { op, operand } => Unary {
+                op: *op,
+                operand: sub(operand),
+            },
+            Angle { unit, operand } => Angle {
+                unit: *unit,
+                operand: sub(operand),
+            },
+            Conditional {
+                condition,
+                then,
+                otherwise,
+            } => Conditional {
+                condition: sub(condition),
+                then: sub(then),
+                otherwise: sub(otherwise),
+            },
+            CallExpresion { args, func } => CallExpresion {
+                args: args.iter().map(|arg| arg.replace(f)).collect(),
+                func: func.clone(),
+            },
+            Bound {
+                func,
+                var,
+                body,
+                args,
+            } => {
+                let inner = |name: &str| if name == var { None } else { f(name) };
+                Bound {
+                    func: *func,
+                    var: var.clone(),
+                    body: body.replace(&inner).into(),
+                    args: args.iter().map(|arg| arg.replace(f)).collect(),
+                }
+            }
+            Variable(name) => f(name).unwrap_or_else(|| Variable(name.clone())),
+            Paren(inner) => Paren(sub(inner)),
+            kind => kind.clone(),
+        };
+        Expression::new(kind, self.span)
+    }
+
+    pub fn derivative(
+        &self,
+        var: &str,
+        mode: AngleMode,
+        context: &Environment,
+    ) -> Result<Expression, ParseError> {
+        self.diff(var, mode, context, 0)
+    }
+
+    fn diff(
+        &self,
+        var: &str,
+        mode: AngleMode,
+        context: &Environment,
+        depth: usize,
+    ) -> Result<Expression, ParseError> {
+        use self::ExprKind::*;
+
+        if !self.depends_on(var) {
+            return Ok(number(0.0, self.span));
+        }
+        let d = |expr: &Expression| expr.diff(var, mode, context, depth);
+
+        match &self.kind {
+            Binary { op, left, right } => {
+                let (u, v) = (&**left, &**right);
+                match (op, &v.kind) {
+                    (
+                        Operator::Plus,
+                        Unary {
+                            op: Operator::Percent,
+                            operand,
+                        },
+                    ) => d(&mul(u.clone(), add(number(1.0, v.span), percent(operand)))),
+                    (
+                        Operator::Minus,
+                        Unary {
+                            op: Operator::Percent,
+                            operand,
+                        },
+                    ) => d(&mul(u.clone(), sub(number(1.0, v.span), percent(operand)))),
+                    _ => self.diff_binary(*op, u, v, var, mode, context, depth),
+                }
+            }
+            Unary { op, operand } => match op {
+                Operator::Neg => Ok(neg(d(operand)?)),
+                Operator::Percent => Ok(div(d(operand)?, number(100.0, self.span))),
+                Operator::Fact => {
+                    let shifted = add((**operand).clone(), number(1.0, operand.span));
+                    d(&call("gamma", vec![shifted], self.span))
+                }
+                Operator::Not => Ok(number(0.0, self.span)),
+                _ => Err(self.non_differentiable(op.symbol())),
+            },
+            Conditional {
+                condition,
+                then,
+                otherwise,
+            } => Ok(Expression::new(
+                Conditional {
+                    condition: condition.clone(),
+                    then: d(then)?.into(),
+                    otherwise: d(otherwise)?.into(),
+                },
+                self.span,
+            )),
+            CallExpresion { args, func } => match context.get(func) {
+                Some(VariableValue::UserFunction { params, body }) => {
+                    if depth >= MAX_CALL_DEPTH {
+                        return Err(ParseError::RecursionLimit(func.clone(), self.span));
+                    }
+                    let body = body.resolve_globals(params, context);
+                    let mut result = number(0.0, self.span);
+                    for (param, arg) in params.iter().zip(args) {
+                        if !arg.depends_on(var) {
+                            continue;
+                        }
+                        let inner = body.diff(param, mode, context, depth + 1)?;
+                        let partial = inner.substitute(params, args);
+                        result = add(result, mul(partial, d(arg)?));
+                    }
+                    Ok(result)
+                }
+                Some(VariableValue::Function(..)) => {
+                    self.diff_builtin(func, args, var, mode, context, depth)
+                }
+                _ => Err(ParseError::NonFunction(func.clone(), self.span)),
+            },
+            Bound {
+                func,
+                var: bound,
+                body,
+                args,
+            } => self.diff_bound(*func, bound, body, args, var, mode, context, depth),
+            Angle { unit, operand } => Ok(mul(number(unit.factor(mode), self.span), d(operand)?)),
+            Variable(_) => Ok(number(1.0, self.span)),
+            Paren(inner) => d(inner),
+            Number(_) | Complex(_) | Rational(_) => Ok(number(0.0, self.span)),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn diff_binary(
+        &self,
+        op: Operator,
+        u: &Expression,
+        v: &Expression,
+        var: &str,
+        mode: AngleMode,
+        context: &Environment,
+        depth: usize,
+    ) -> Result<Expression, ParseError> {
+        use self::Operator::*;
+
+        let d = |expr: &Expression| expr.diff(var, mode, context, depth);
+        match op {
+            Plus => Ok(add(d(u)?, d(v)?)),
+            Minus => Ok(sub(d(u)?, d(v)?)),
+            Mult => Ok(add(mul(d(u)?, v.clone()), mul(u.clone(), d(v)?))),
+            Div if !v.depends_on(var) => Ok(div(d(u)?, v.clone())),
+            Div => {
+                let numerator = sub(mul(d(u)?, v.clone()), mul(u.clone(), d(v)?));
+                Ok(div(numerator, pow(v.clone(), number(2.0, v.span))))
+            }
+            Exp if !v.depends_on(var) => {
+                let power = sub(v.clone(), number(1.0, v.span));
+                Ok(mul(mul(v.clone(), pow(u.clone(), power)), d(u)?))
+            }
+            Exp if !u.depends_on(var) => {
+                let ln = call("ln", vec![u.clone()], u.span);
+                Ok(mul(mul(self.clone(), ln), d(v)?))
+            }
+            Exp => {
+                let ln = call("ln", vec![u.clone()], u.span);
+                let inner = add(mul(d(v)?, ln), div(mul(v.clone(), d(u)?), u.clone()));
+                Ok(mul(self.clone(), inner))
+            }
+            Mod => {
+                let quotient = call("floor", vec![div(u.clone(), v.clone())], self.span);
+                Ok(sub(d(u)?, mul(d(v)?, quotient)))
+            }
+            IntDiv | Less | LessEqual | Greater | GreaterEqual | Equal | NotEqual | And | Or => {
+                Ok(number(0.0, self.span))
+            }
+            _ => Err(self.non_differentiable(op.symbol())),
+        }
+    }
+
+    fn diff_builtin(
+        &self,
+        func: &str,
+        args: &[Expression],
+        var: &str,
+        mode: AngleMode,
+        context: &Environment,
+        depth: usize,
+    ) -> Result<Expression, ParseError> {
+        let span = self.span;
+        let d = |expr: &Expression| expr.diff(var, mode, context, depth);
+        let f = |name: &str, args: &[&Expression]| {
+            call(name, args.iter().map(|arg| (*arg).clone()).collect(), span)
+        };
+        let scale = mode.to_radians(1.0);
+        let square = |expr: &Expression| pow(expr.clone(), number(2.0, span));
+        let one = || number(1.0, span);
+
+        match (func, args) {
+            ("sin", [u]) => Ok(mul(mul(f("cos", &[u]), number(scale, span)), d(u)?)),
+            ("cos", [u]) => Ok(neg(mul(mul(f("sin", &[u]), number(scale, span)), d(u)?))),
+            ("tan", [u]) => {
+                let sec = square(&f("cos", &[u]));
+                Ok(div(mul(number(scale, span), d(u)?), sec))
+            }
+            ("asin", [u]) | ("acos", [u]) => {
+                let root = f("sqrt", &[&sub(one(), square(u))]);
+                let value = div(d(u)?, mul(number(scale, span), root));
+                Ok(if func == "acos" { neg(value) } else { value })
+            }
+            ("atan", [u]) => {
+                let denominator = mul(number(scale, span), add(one(), square(u)));
+                Ok(div(d(u)?, denominator))
+            }
+            ("atan2", [y, x]) => {
+                let numerator = sub(mul(x.clone(), d(y)?), mul(y.clone(), d(x)?));
+                let denominator = mul(number(scale, span), add(square(x), square(y)));
+                Ok(div(numerator, denominator))
+            }
+            ("hypot", [x, y]) => {
+                let numerator = add(mul(x.clone(), d(x)?), mul(y.clone(), d(y)?));
+                Ok(div(numerator, self.clone()))
+            }
+            ("min", [u]) | ("max", [u]) => d(u),
+            ("min", [u, rest @ ..]) | ("max", [u, rest @ ..]) => {
+                let rest = call(func, rest.to_vec(), span);
+                let op = if func == "min" {
+                    Operator::LessEqual
+                } else {
+                    Operator::GreaterEqual
+                };
+                Ok(Expression::new(
+                    ExprKind::Conditional {
+                        condition: binary(op, u.clone(), rest.clone()).into(),
+                        then: d(u)?.into(),
+                        otherwise: d(&rest)?.into(),
+                    },
+                    span,
+                ))
+            }
+            ("root", [u, n]) => {
+                let ln = f("ln", &[&f("abs", &[u])]);
+                let inner = sub(
+                    div(d(u)?, mul(n.clone(), u.clone())),
+                    div(mul(d(n)?, ln), square(n)),
+                );
+                Ok(mul(self.clone(), inner))
+            }
+            ("ceil", [_]) | ("floor", [_]) | ("round", [_]) => Ok(number(0.0, span)),
+            ("ln", [u]) => Ok(div(d(u)?, u.clone())),
+            ("log", [u]) => d(&div(f("ln", &[u]), number(LN_10, span))),
+            ("log", [u, base]) => d(&div(f("ln", &[u]), f("ln", &[base]))),
+            ("abs", [u]) => Ok(mul(d(u)?, div(u.clone(), self.clone()))),
+            ("sqrt", [u]) => Ok(div(d(u)?, mul(number(2.0, span), self.clone()))),
+            ("gamma", [u]) => {
+                let digamma = f("polygamma", &[&number(0.0, span), u]);
+                Ok(mul(mul(self.clone(), digamma), d(u)?))
+            }
+            ("polygamma", [n, u]) if !n.depends_on(var) => {
+                let order = add(n.clone(), one());
+                Ok(mul(f("polygamma", &[&order, u]), d(u)?))
+            }
+            _ => Err(self.non_differentiable(func)),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn diff_bound(
+        &self,
+        func: BoundFunc,
+        bound: &str,
+        body: &Expression,
+        args: &[Expression],
+        var: &str,
+        mode: AngleMode,
+        context: &Environment,
+        depth: usize,
+    ) -> Result<Expression, ParseError> {
+        let d = |expr: &Expression| expr.diff(var, mode, context, depth);
+        let partial = if bound == var {
+            number(0.0, body.span)
+        } else {
+            d(body)?
+        };
+        let params = [bound.to_string()];
+        let at = |value: &Expression| body.substitute(&params, ::std::slice::from_ref(value));
+        let over = |func: BoundFunc, body: Expression| {
+            let kind = ExprKind::Bound {
+                func,
+                var: bound.to_string(),
+                body: body.into(),
+                args: args.to_vec(),
+            };
+            Expression::new(kind, self.span)
+        };
+
+        match func {
+            BoundFunc::Solve => {
+                let implicit = div(partial, body.diff(bound, mode, context, depth)?);
+                Ok(neg(implicit).substitute(&params, ::std::slice::from_ref(self)))
+            }
+            BoundFunc::Integrate => {
+                let (a, b) = (&args[0], &args[1]);
+                let limits = sub(mul(at(b), d(b)?), mul(at(a), d(a)?));
+                if is_number(&partial, 0.0) {
+                    Ok(limits)
+                } else {
+                    Ok(add(limits, over(BoundFunc::Integrate, partial)))
+                }
+            }
+            _ if args.iter().any(|arg| arg.depends_on(var)) => {
+                Err(self.non_differentiable(func.name()))
+            }
+            BoundFunc::Sum => Ok(over(BoundFunc::Sum, partial)),
+            BoundFunc::Prod => {
+                let logarithmic = over(BoundFunc::Sum, div(partial, body.clone()));
+                Ok(mul(self.clone(), logarithmic))
+            }
+        }
+    }
+
+    fn non_differentiable(&self, name: &str) -> ParseError {
+        ParseError::NonDifferentiable(name.to_string(), self.span)
+    }
+}
+
+fn percent(operand: &Expression) -> Expression {
+    div(operand.clone(), number(100.0, operand.span))
+}
@@ -0,0 +1,62 @@
+use super::ast::CONDITIONAL;
+use super::backend::BackendKind;
+use super::complex::{IMAGINARY, IMAGINARY_UNIT};
+use super::{IntegerMode, VariableValue, DEFAULT_VARS};
+use std::collections::HashMap;
+
+#[derive(Debug, Default)]
+pub struct Environment {
+    vars: HashMap<String, VariableValue>,
+    integer_mode: IntegerMode,
+    backend: BackendKind,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_builtin(&self, name: &str) -> bool {
+        DEFAULT_VARS.contains_key(name) || name == CONDITIONAL || self.is_imaginary(name)
+    }
+
+    fn is_imaginary(&self, name: &str) -> bool {
+        self.is_complex() && name == IMAGINARY
+    }
+
+    pub fn get(&self, name: &str) -> Option<&VariableValue> {
+        DEFAULT_VARS
+            .get(name)
+            .or_else(|| Some(&IMAGINARY_UNIT).filter(|_| self.is_imaginary(name)))
+            .or_else(|| self.vars.get(name))
+    }
+
+    pub fn is_complex(&self) -> bool {
+        self.backend == BackendKind::Complex
+    }
+
+    pub fn backend(&self) -> BackendKind {
+        self.backend
+    }
+
+    pub fn set_backend(&mut self, backend: BackendKind) {
+        self.backend = backend;
+    }
+
+    pub fn integer_mode(&self) -> IntegerMode {
+        self.integer_mode
+    }
+
+    pub fn set_integer_mode(&mut self, mode: IntegerMode) {
+        self.integer_mode = mode;
+    }
+
+    pub fn set(&mut self, name: String, value: VariableValue) -> bool {
+        if self.is_builtin(&name) {
+            false
+        } else {
+            self.vars.insert(name, value);
+            true
+        }
+    }
+}
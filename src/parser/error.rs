@@ -0,0 +1,187 @@
+use super::ast::{EvalError, ParseError};
+use super::lex::{LexError, Span, TokenKind};
+use super::Arity;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalcError {
+    IncompleteExpression(Span),
+    UnexpectedCharacter(char, Span),
+    EmptyParentheses(Span),
+    UnexpectedEOF(Span),
+    UnexpectedToken(TokenKind, Span),
+    UndefinedIdent(String, Span),
+    NonFunction(String, Span),
+    BuiltinAssignment(String, Span),
+    RecursionLimit(String, Span),
+    NonReal(String, Span),
+    ComplexValue(Span),
+    NonDifferentiable(String, Span),
+    NoConvergence(String, Span),
+    IterationLimit(String, Span),
+    SolveVariables(Vec<String>, Span),
+    ArgumentCount {
+        func: String,
+        expected: Arity,
+        found: usize,
+        span: Span,
+    },
+    DivisionByZero(Span),
+    Domain(String, f64, Span),
+    Overflow(Span),
+    Underflow(Span),
+    InexactInteger(Span),
+}
+
+impl CalcError {
+    pub fn span(&self) -> Span {
+        use self::CalcError::*;
+
+        match self {
+            IncompleteExpression(span)
+            | UnexpectedCharacter(_, span)
+            | EmptyParentheses(span)
+            | UnexpectedEOF(span)
+            | UnexpectedToken(_, span)
+            | UndefinedIdent(_, span)
+            | NonFunction(_, span)
+            | BuiltinAssignment(_, span)
+            | RecursionLimit(_, span)
+            | NonReal(_, span)
+            | ComplexValue(span)
+            | NonDifferentiable(_, span)
+            | NoConvergence(_, span)
+            | IterationLimit(_, span)
+            | SolveVariables(_, span)
+            | ArgumentCount { span, .. }
+            | DivisionByZero(span)
+            | Domain(_, _, span)
+            | Overflow(span)
+            | Underflow(span)
+            | InexactInteger(span) => *span,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        use self::CalcError::*;
+
+        match self {
+            IncompleteExpression(_) => "IncompleteExpression",
+            UnexpectedCharacter(..) => "UnexpectedCharacter",
+            EmptyParentheses(_) => "EmptyParentheses",
+            UnexpectedEOF(_) => "UnexpectedEOF",
+            UnexpectedToken(..) => "UnexpectedToken",
+            UndefinedIdent(..) => "UndefinedIdent",
+            NonFunction(..) => "NonFunction",
+            BuiltinAssignment(..) => "BuiltinAssignment",
+            RecursionLimit(..) => "RecursionLimit",
+            NonReal(..) => "NonReal",
+            ComplexValue(_) => "ComplexValue",
+            NonDifferentiable(..) => "NonDifferentiable",
+            NoConvergence(..) => "NoConvergence",
+            IterationLimit(..) => "IterationLimit",
+            SolveVariables(..) => "SolveVariables",
+            ArgumentCount { .. } => "ArgumentCount",
+            DivisionByZero(_) => "DivisionByZero",
+            Domain(..) => "Domain",
+            Overflow(_) => "Overflow",
+            Underflow(_) => "Underflow",
+            InexactInteger(_) => "InexactInteger",
+        }
+    }
+}
+
+impl From<LexError> for CalcError {
+    fn from(e: LexError) -> Self {
+        match e {
+            LexError::UnexpectedEOF(span) => CalcError::IncompleteExpression(span),
+            LexError::EmptyParentheses(span) => CalcError::EmptyParentheses(span),
+            LexError::InexactInteger(span) => CalcError::InexactInteger(span),
+            LexError::UnexpectedCharacter {
+                character,
+                position,
+            } => CalcError::UnexpectedCharacter(character, Span::at(position)),
+        }
+    }
+}
+
+impl From<ParseError> for CalcError {
+    fn from(e: ParseError) -> Self {
+        use self::ParseError::*;
+
+        match e {
+            UnexpectedEOF(span) => CalcError::UnexpectedEOF(span),
+            UnexpectedToken(token) => CalcError::UnexpectedToken(token.kind, token.span),
+            UndefinedIdent(ident, span) => CalcError::UndefinedIdent(ident, span),
+            NonFunction(ident, span) => CalcError::NonFunction(ident, span),
+            BuiltinAssignment(ident, span) => CalcError::BuiltinAssignment(ident, span),
+            RecursionLimit(ident, span) => CalcError::RecursionLimit(ident, span),
+            NonReal(ident, span) => CalcError::NonReal(ident, span),
+            ComplexValue(span) => CalcError::ComplexValue(span),
+            NonDifferentiable(name, span) => CalcError::NonDifferentiable(name, span),
+            NoConvergence(name, span) => CalcError::NoConvergence(name, span),
+            IterationLimit(name, span) => CalcError::IterationLimit(name, span),
+            ArgumentCount {
+                func,
+                expected,
+                found,
+                span,
+            } => CalcError::ArgumentCount {
+                func,
+                expected,
+                found,
+                span,
+            },
+            Eval(EvalError::DivisionByZero, span) => CalcError::DivisionByZero(span),
+            Eval(EvalError::Domain(func, value), span) => CalcError::Domain(func, value, span),
+            Eval(EvalError::Overflow, span) => CalcError::Overflow(span),
+            Eval(EvalError::Underflow, span) => CalcError::Underflow(span),
+            Eval(EvalError::InexactInteger, span) => CalcError::InexactInteger(span),
+        }
+    }
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::CalcError::*;
+
+        match self {
+            IncompleteExpression(_) => write!(f, "Incomplete expression"),
+            UnexpectedCharacter(character, span) => write!(
+                f,
+                "Unexpected character '{}' at index {}",
+                character, span.start
+            ),
+            EmptyParentheses(_) => write!(f, "Empty parentheses"),
+            UnexpectedEOF(_) => write!(f, "Unexpected end of file"),
+            UnexpectedToken(kind, _) => write!(f, "Unexpected {}", kind.descriptor()),
+            UndefinedIdent(ident, _) => write!(f, "Undefined variable \"{}\"", ident),
+            NonFunction(ident, _) => write!(f, "\"{}\" is not a function", ident),
+            BuiltinAssignment(ident, _) => write!(f, "Cannot assign to built-in \"{}\"", ident),
+            RecursionLimit(ident, _) => write!(f, "Recursion limit exceeded in \"{}\"", ident),
+            NonReal(ident, _) => write!(f, "\"{}\" is undefined for complex values", ident),
+            ComplexValue(_) => write!(f, "Complex values require complex mode"),
+            NonDifferentiable(name, _) => write!(f, "\"{}\" cannot be differentiated", name),
+            NoConvergence(name, _) => write!(f, "\"{}\" did not converge", name),
+            IterationLimit(name, _) => write!(f, "Iteration limit exceeded in \"{}\"", name),
+            SolveVariables(vars, _) if vars.is_empty() => write!(f, "No variable to solve for"),
+            SolveVariables(vars, _) => {
+                write!(f, "Cannot solve for several variables: {}", vars.join(", "))
+            }
+            ArgumentCount {
+                func,
+                expected,
+                found,
+                ..
+            } => write!(f, "\"{}\" expects {}, found {}", func, expected, found),
+            DivisionByZero(_) => write!(f, "Division by zero"),
+            Domain(func, value, _) => write!(f, "\"{}\" is undefined for {}", func, value),
+            Overflow(_) => write!(f, "Result is too large"),
+            Underflow(_) => write!(f, "Result is too small"),
+            InexactInteger(_) => write!(f, "Integer is too large to represent exactly"),
+        }
+    }
+}
+
+impl Error for CalcError {}
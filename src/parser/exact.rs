@@ -0,0 +1,268 @@
+use super::backend::Backend;
+use super::lex::Operator;
+use super::{AngleMode, VariableValue};
+use bigdecimal::{BigDecimal, Context};
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+
+pub const DEFAULT_PRECISION: u64 = 50;
+
+const MAX_EXPONENT: i32 = 10_000;
+const MAX_BITS: u64 = 100_000;
+const MAX_FACTORIAL: u32 = 5_000;
+const GUARD_DIGITS: u64 = 5;
+
+fn truth(value: bool) -> BigRational {
+    if value {
+        BigRational::one()
+    } else {
+        BigRational::zero()
+    }
+}
+
+fn to_rational(value: &BigDecimal) -> BigRational {
+    let (digits, scale) = value.as_bigint_and_exponent();
+    let ten = BigInt::from(10);
+    if scale >= 0 {
+        BigRational::new(digits, num_traits::pow(ten, scale as usize))
+    } else {
+        BigRational::from_integer(digits * num_traits::pow(ten, (-scale) as usize))
+    }
+}
+
+fn parse_real(value: f64) -> Option<BigDecimal> {
+    Some(value)
+        .filter(|value| value.is_finite())
+        .and_then(|value| value.to_string().parse().ok())
+}
+
+// Exact integer powers whose result would need more than MAX_BITS to store
+fn is_too_large(op: Operator, left: &BigRational, right: &BigRational) -> bool {
+    let bits = left.numer().bits().max(left.denom().bits());
+    op == Operator::Exp
+        && right.is_integer()
+        && right
+            .to_integer()
+            .to_i32()
+            .filter(|exp| exp.abs() <= MAX_EXPONENT)
+            .is_some_and(|exp| u64::from(exp.unsigned_abs()) * bits > MAX_BITS)
+}
+
+fn integer_sqrt(value: &BigInt) -> Option<BigInt> {
+    Some(value.sqrt()).filter(|root| root * root == *value)
+}
+
+fn format_fixed(digits: &BigInt, scale: usize) -> String {
+    let sign = if digits.is_negative() { "-" } else { "" };
+    let digits = digits.abs().to_string();
+    let digits = format!("{:0>width$}", digits, width = scale + 1);
+    let (int, fract) = digits.split_at(digits.len() - scale);
+    let fract = fract.trim_end_matches('0');
+    if fract.is_empty() {
+        format!("{}{}", sign, int)
+    } else {
+        format!("{}{}.{}", sign, int, fract)
+    }
+}
+
+pub fn format_rational(value: &BigRational) -> String {
+    let mut denom = value.denom().clone();
+    let mut scale = 0;
+    for factor in &[2, 5] {
+        let factor = BigInt::from(*factor);
+        let mut count = 0;
+        while (&denom % &factor).is_zero() {
+            denom /= &factor;
+            count += 1;
+        }
+        scale = scale.max(count);
+    }
+
+    if denom.is_one() {
+        let digits = value * BigRational::from_integer(num_traits::pow(BigInt::from(10), scale));
+        format_fixed(&digits.to_integer(), scale)
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn format_decimal(value: &BigDecimal) -> String {
+    let (digits, scale) = value.as_bigint_and_exponent();
+    if scale >= 0 {
+        format_fixed(&digits, scale as usize)
+    } else {
+        format_fixed(
+            &(digits * num_traits::pow(BigInt::from(10), (-scale) as usize)),
+            0,
+        )
+    }
+}
+
+fn apply(op: Operator, left: &BigRational, right: &BigRational) -> Option<BigRational> {
+    match op {
+        Operator::Plus => Some(left + right),
+        Operator::Minus => Some(left - right),
+        Operator::Mult => Some(left * right),
+        Operator::Div if !right.is_zero() => Some(left / right),
+        Operator::Mod if !right.is_zero() => Some(left - right * (left / right).floor()),
+        Operator::IntDiv if !right.is_zero() => Some((left / right).floor()),
+        Operator::Exp if right.is_integer() => right
+            .to_integer()
+            .to_i32()
+            .filter(|exp| exp.abs() <= MAX_EXPONENT)
+            .filter(|exp| *exp >= 0 || !left.is_zero())
+            .filter(|_| !is_too_large(op, left, right))
+            .map(|exp| left.pow(exp)),
+        Operator::Less => Some(truth(left < right)),
+        Operator::LessEqual => Some(truth(left <= right)),
+        Operator::Greater => Some(truth(left > right)),
+        Operator::GreaterEqual => Some(truth(left >= right)),
+        Operator::Equal => Some(truth(left == right)),
+        Operator::NotEqual => Some(truth(left != right)),
+        _ => None,
+    }
+}
+
+fn apply_unary(op: Operator, value: &BigRational) -> Option<BigRational> {
+    match op {
+        Operator::Neg => Some(-value),
+        Operator::Percent => Some(value / BigRational::from_integer(BigInt::from(100))),
+        Operator::Fact if value.is_integer() => value
+            .to_integer()
+            .to_u32()
+            .filter(|n| *n <= MAX_FACTORIAL)
+            .map(|n| BigRational::from_integer((1..=n).map(BigInt::from).product())),
+        _ => None,
+    }
+}
+
+fn call(func: &str, args: &[BigRational]) -> Option<BigRational> {
+    match (func, args) {
+        ("abs", [x]) => Some(x.abs()),
+        ("floor", [x]) => Some(x.floor()),
+        ("ceil", [x]) => Some(x.ceil()),
+        ("round", [x]) => Some(x.round()),
+        ("min", _) => args.iter().min().cloned(),
+        ("max", _) => args.iter().max().cloned(),
+        ("sqrt", [x]) if !x.is_negative() => integer_sqrt(x.numer())
+            .and_then(|numer| integer_sqrt(x.denom()).map(|denom| BigRational::new(numer, denom))),
+        _ => None,
+    }
+}
+
+pub struct RationalBackend;
+
+impl Backend for RationalBackend {
+    type Value = BigRational;
+
+    fn convert_real(&self, value: f64) -> Option<BigRational> {
+        parse_real(value).map(|value| to_rational(&value))
+    }
+
+    fn convert_complex(&self, value: Complex64) -> Option<BigRational> {
+        Some(value.re)
+            .filter(|_| value.im == 0.0)
+            .and_then(|re| self.convert_real(re))
+    }
+
+    fn convert_rational(&self, value: &BigRational) -> Option<BigRational> {
+        Some(value.clone())
+    }
+
+    fn to_real(&self, value: &BigRational) -> Option<f64> {
+        value.to_f64()
+    }
+
+    fn is_too_large(&self, op: Operator, left: &BigRational, right: &BigRational) -> bool {
+        is_too_large(op, left, right)
+    }
+
+    fn apply(&self, op: Operator, left: &BigRational, right: &BigRational) -> Option<BigRational> {
+        apply(op, left, right)
+    }
+
+    fn apply_unary(&self, op: Operator, value: &BigRational) -> Option<BigRational> {
+        apply_unary(op, value)
+    }
+
+    fn call(&self, func: &str, args: &[BigRational], _: AngleMode) -> Option<BigRational> {
+        call(func, args)
+    }
+
+    fn to_variable(&self, value: BigRational) -> VariableValue {
+        VariableValue::Rational(value)
+    }
+}
+
+pub struct DecimalBackend {
+    context: Context,
+}
+
+impl DecimalBackend {
+    pub fn new(precision: u64) -> Self {
+        Self {
+            context: Context::default().with_prec(precision).unwrap_or_default(),
+        }
+    }
+
+    fn round(&self, value: &BigRational) -> BigDecimal {
+        let numer = BigDecimal::from(value.numer().clone());
+        if value.is_integer() {
+            return self.context.round_decimal(numer);
+        }
+        let guard = self
+            .context
+            .with_prec(self.context.precision().get() + GUARD_DIGITS)
+            .unwrap_or_else(|| self.context.clone());
+        let inverse = BigDecimal::from(value.denom().clone()).inverse_with_context(&guard);
+        self.context.round_decimal(numer * inverse)
+    }
+}
+
+impl Backend for DecimalBackend {
+    type Value = BigDecimal;
+
+    fn convert_real(&self, value: f64) -> Option<BigDecimal> {
+        parse_real(value).map(|value| self.context.round_decimal(value))
+    }
+
+    fn convert_complex(&self, value: Complex64) -> Option<BigDecimal> {
+        Some(value.re)
+            .filter(|_| value.im == 0.0)
+            .and_then(|re| self.convert_real(re))
+    }
+
+    fn convert_rational(&self, value: &BigRational) -> Option<BigDecimal> {
+        Some(self.round(value))
+    }
+
+    fn to_real(&self, value: &BigDecimal) -> Option<f64> {
+        value.to_f64()
+    }
+
+    fn is_too_large(&self, op: Operator, left: &BigDecimal, right: &BigDecimal) -> bool {
+        is_too_large(op, &to_rational(left), &to_rational(right))
+    }
+
+    fn apply(&self, op: Operator, left: &BigDecimal, right: &BigDecimal) -> Option<BigDecimal> {
+        apply(op, &to_rational(left), &to_rational(right)).map(|value| self.round(&value))
+    }
+
+    fn apply_unary(&self, op: Operator, value: &BigDecimal) -> Option<BigDecimal> {
+        apply_unary(op, &to_rational(value)).map(|value| self.round(&value))
+    }
+
+    fn call(&self, func: &str, args: &[BigDecimal], _: AngleMode) -> Option<BigDecimal> {
+        match (func, args) {
+            ("sqrt", [x]) => x.sqrt_with_context(&self.context),
+            _ => call(func, &args.iter().map(to_rational).collect::<Vec<_>>())
+                .map(|value| self.round(&value)),
+        }
+    }
+
+    fn to_variable(&self, value: BigDecimal) -> VariableValue {
+        VariableValue::Rational(to_rational(&value))
+    }
+}
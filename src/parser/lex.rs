@@ -1,161 +1,374 @@
 use std::borrow::Cow;
 
 simple_enum! {
-    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
     OperatorPrecedence {
+        Or,
+        And,
+        Not,
+        Comparison,
+        BitOr,
+        BitXor,
+        BitAnd,
+        Shift,
         PlusMinus,
         MultDiv,
-        Exp
+        Negation,
+        Exp,
+        Postfix
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Associativity {
+    Left,
+    Right,
+    Neither,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fixity {
+    Prefix,
+    Infix,
+    Postfix,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Operator {
     Exp,
     Mult,
     Div,
     Plus,
     Minus,
+    Neg,
+    Fact,
+    Mod,
+    IntDiv,
+    Percent,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+    Not,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
+}
+
+#[derive(Debug)]
+pub struct OperatorInfo {
+    pub symbol: &'static str,
+    pub op: Operator,
+    pub fixity: Fixity,
+    pub precedence: OperatorPrecedence,
+    pub associativity: Associativity,
+}
+
+operator_table! {
+    OPERATORS {
+        "or" => Or: Infix(Or, Left),
+        "and" => And: Infix(And, Left),
+        "not" => Not: Prefix(Not, Right),
+        "<" => Less: Infix(Comparison, Neither),
+        "<=" => LessEqual: Infix(Comparison, Neither),
+        ">" => Greater: Infix(Comparison, Neither),
+        ">=" => GreaterEqual: Infix(Comparison, Neither),
+        "==" => Equal: Infix(Comparison, Neither),
+        "!=" => NotEqual: Infix(Comparison, Neither),
+        "|" => BitOr: Infix(BitOr, Left),
+        "xor" => BitXor: Infix(BitXor, Left),
+        "&" => BitAnd: Infix(BitAnd, Left),
+        "<<" => ShiftLeft: Infix(Shift, Left),
+        ">>" => ShiftRight: Infix(Shift, Left),
+        "+" => Plus: Infix(PlusMinus, Left),
+        "-" => Minus: Infix(PlusMinus, Left),
+        "*" => Mult: Infix(MultDiv, Left),
+        "/" => Div: Infix(MultDiv, Left),
+        "%" => Mod: Infix(MultDiv, Left),
+        "mod" => Mod: Infix(MultDiv, Left),
+        "//" => IntDiv: Infix(MultDiv, Left),
+        "-" => Neg: Prefix(Negation, Right),
+        "~" => BitNot: Prefix(Negation, Right),
+        "^" => Exp: Infix(Exp, Right),
+        "!" => Fact: Postfix(Postfix, Left),
+        "%" => Percent: Postfix(Postfix, Left)
+    }
+}
+
+impl OperatorInfo {
+    pub fn find(symbol: &str, fixity: Fixity) -> Option<&'static OperatorInfo> {
+        OPERATORS
+            .iter()
+            .find(|info| info.symbol == symbol && info.fixity == fixity)
+    }
+
+    pub fn is_prefix_start(c: char) -> bool {
+        OPERATORS
+            .iter()
+            .any(|info| info.fixity == Fixity::Prefix && info.symbol.starts_with(c))
+    }
+
+    pub fn has_postfix(symbol: &str) -> bool {
+        OPERATORS
+            .iter()
+            .any(|info| info.symbol == symbol && info.fixity == Fixity::Postfix)
+    }
+
+    fn is_word(&self) -> bool {
+        self.symbol.chars().all(char::is_alphabetic)
+    }
+
+    fn find_word(word: &str) -> Option<&'static str> {
+        OPERATORS
+            .iter()
+            .find(|info| info.is_word() && info.symbol == word)
+            .map(|info| info.symbol)
+    }
+
+    fn is_symbol_prefix(s: &str) -> bool {
+        OPERATORS
+            .iter()
+            .any(|info| !info.is_word() && info.symbol.starts_with(s))
+    }
 }
 
 impl Operator {
+    pub fn info(&self) -> Option<&'static OperatorInfo> {
+        OPERATORS.iter().find(|info| info.op == *self)
+    }
+
     pub fn precedence(&self) -> OperatorPrecedence {
+        self.info()
+            .map(|info| info.precedence)
+            .unwrap_or(OperatorPrecedence::Exp)
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        self.info().map(|info| info.symbol).unwrap_or("?")
+    }
+
+    pub fn apply_unary(&self, value: f64) -> Option<f64> {
+        match self {
+            Operator::Neg => Some(-value),
+            Operator::Fact => super::factorial(value),
+            Operator::Percent => Some(value / 100.0),
+            Operator::Not => Some(truth(value == 0.0)),
+            _ => Some(f64::NAN),
+        }
+    }
+
+    pub fn apply(&self, left: f64, right: f64) -> f64 {
         use self::Operator::*;
 
         match self {
-            Exp => OperatorPrecedence::Exp,
-            Mult | Div => OperatorPrecedence::MultDiv,
-            Plus | Minus => OperatorPrecedence::PlusMinus,
+            Exp => left.powf(right),
+            Mult => left * right,
+            Div => left / right,
+            Plus => left + right,
+            Minus => left - right,
+            Mod => left - right * (left / right).floor(),
+            IntDiv => (left / right).floor(),
+            Less => truth(left < right),
+            LessEqual => truth(left <= right),
+            Greater => truth(left > right),
+            GreaterEqual => truth(left >= right),
+            Equal => truth(left == right),
+            NotEqual => truth(left != right),
+            And => truth(left != 0.0 && right != 0.0),
+            Or => truth(left != 0.0 || right != 0.0),
+            Neg | Fact | Percent | Not | BitNot => f64::NAN,
+            BitAnd | BitOr | BitXor | ShiftLeft | ShiftRight => f64::NAN,
         }
     }
 
-    pub fn from_char(c: char) -> Option<Operator> {
+    pub fn is_bitwise(&self) -> bool {
         use self::Operator::*;
 
-        Some(match c {
-            '^' => Exp,
-            '*' => Mult,
-            '/' => Div,
-            '+' => Plus,
-            '-' => Minus,
-            _ => return None,
-        })
+        matches!(
+            self,
+            BitAnd | BitOr | BitXor | BitNot | ShiftLeft | ShiftRight
+        )
     }
 
-    pub fn get_char(&self) -> char {
+    pub fn apply_integer(&self, left: i128, right: i128) -> Option<i128> {
         use self::Operator::*;
 
+        let shift = Some(right).filter(|r| (0..128).contains(r)).map(|r| r as u32);
         match self {
-            Exp => '^',
-            Mult => '*',
-            Div => '/',
-            Plus => '+',
-            Minus => '-',
+            BitAnd => Some(left & right),
+            BitOr => Some(left | right),
+            BitXor => Some(left ^ right),
+            BitNot => Some(!left),
+            ShiftLeft => shift.map(|s| left << s),
+            ShiftRight => shift.map(|s| left >> s),
+            _ => None,
         }
     }
 
-    pub fn is_operator(c: char) -> bool {
-        Self::from_char(c).is_some()
+    pub fn divides_by_zero(&self, left: Option<f64>, right: Option<f64>) -> bool {
+        use self::Operator::*;
+
+        match self {
+            Div | Mod | IntDiv => right == Some(0.0),
+            Exp => left == Some(0.0) && right.is_some_and(|r| r < 0.0),
+            _ => false,
+        }
     }
 
-    pub fn apply(&self, left: f64, right: f64) -> f64 {
+    pub fn can_underflow(&self) -> bool {
         use self::Operator::*;
 
+        matches!(self, Mult | Div | Exp)
+    }
+
+    pub fn short_circuit(&self, left: f64) -> Option<f64> {
         match self {
-            Exp => left.powf(right),
-            Mult => left * right,
-            Div => left / right,
-            Plus => left + right,
-            Minus => left - right,
+            Operator::And if left == 0.0 => Some(0.0),
+            Operator::Or if left != 0.0 => Some(1.0),
+            _ => None,
         }
     }
 }
 
-#[derive(PartialEq, Debug)]
-pub enum Token {
+fn truth(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn at(position: usize) -> Self {
+        Self::new(position, position + 1)
+    }
+
+    pub fn to(self, other: Span) -> Self {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum TokenKind {
     Number(f64),
-    Op(Operator),
+    Op(&'static str),
     Var(String),
-    Parentheses(Vec<Token>),
-    Negation,
+    OpenParen,
+    CloseParen,
+    Assign,
+    Comma,
+    Degree,
 }
 
-impl Token {
-    pub fn is_op(&self) -> bool {
+impl TokenKind {
+    pub fn descriptor(&self) -> Cow<'static, str> {
+        use self::TokenKind::*;
+
         match self {
-            Token::Op(_) => true,
-            _ => false,
+            Number(n) => format!("number {}", n).into(),
+            Var(name) => format!("variable {}", name).into(),
+            Op(symbol) => format!("operator {}", symbol).into(),
+            OpenParen => Cow::Borrowed("token '('"),
+            CloseParen => Cow::Borrowed("token ')'"),
+            Assign => Cow::Borrowed("token '='"),
+            Comma => Cow::Borrowed("token ','"),
+            Degree => Cow::Borrowed("token '°'"),
         }
     }
+}
 
-    pub fn is_neg(&self) -> bool {
-        *self == Token::Negation
+#[derive(PartialEq, Debug)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Token {
+    pub fn new(kind: TokenKind, span: Span) -> Self {
+        Self { kind, span }
     }
 
-    pub fn is_num(&self) -> bool {
-        match self {
-            Token::Number(_) => true,
-            _ => false,
-        }
+    pub fn is_op(&self) -> bool {
+        matches!(self.kind, TokenKind::Op(_))
     }
 
-    pub fn is_var(&self) -> bool {
-        match self {
-            Token::Var(_) => true,
+    pub fn expects_operand(&self) -> bool {
+        match self.kind {
+            TokenKind::Op(symbol) => !OperatorInfo::has_postfix(symbol),
+            TokenKind::OpenParen | TokenKind::Assign | TokenKind::Comma => true,
             _ => false,
         }
     }
 
-    pub fn is_paren(&self) -> bool {
-        match self {
-            Token::Parentheses(_) => true,
+    pub fn starts_operand(&self) -> bool {
+        match self.kind {
+            TokenKind::Op(symbol) => OperatorInfo::find(symbol, Fixity::Prefix).is_some(),
+            TokenKind::Number(_) | TokenKind::Var(_) | TokenKind::OpenParen => true,
             _ => false,
         }
     }
 
-    pub fn get_descriptor(&self) -> Cow<'static, str> {
-        use self::Token::*;
+    pub fn is_assign(&self) -> bool {
+        self.kind == TokenKind::Assign
+    }
 
-        match self {
-            Number(n) => format!("number {}", n).into(),
-            Parentheses(_) => Cow::Borrowed("parentheses expression"),
-            Var(name) => format!("variable {}", name).into(),
-            Op(op) => format!("operator {}", op.get_char()).into(),
-            Negation => Cow::Borrowed("token '-'"),
-        }
+    pub fn is_comma(&self) -> bool {
+        self.kind == TokenKind::Comma
+    }
+
+    pub fn is_num(&self) -> bool {
+        matches!(self.kind, TokenKind::Number(_))
+    }
+
+    pub fn is_var(&self) -> bool {
+        matches!(self.kind, TokenKind::Var(_))
+    }
+
+    pub fn is_open_paren(&self) -> bool {
+        self.kind == TokenKind::OpenParen
+    }
+
+    pub fn is_close_paren(&self) -> bool {
+        self.kind == TokenKind::CloseParen
     }
 }
 
 #[derive(Clone, Debug)]
 pub enum LexError {
-    UnexpectedEOF,
-    EmptyParentheses,
+    UnexpectedEOF(Span),
+    EmptyParentheses(Span),
+    InexactInteger(Span),
     UnexpectedCharacter { character: char, position: usize },
 }
 
 impl LexError {
     pub fn is_eof(&self) -> bool {
-        match self {
-            LexError::UnexpectedEOF => true,
-            _ => false,
-        }
+        matches!(self, LexError::UnexpectedEOF(_))
     }
-}
 
-impl From<LexError> for Cow<'static, str> {
-    fn from(e: LexError) -> Cow<'static, str> {
-        use self::LexError::*;
-
-        match e {
-            UnexpectedEOF => Cow::Borrowed("Incomplete expression"),
-            UnexpectedCharacter {
-                character,
-                position,
-            } => Cow::Owned(format!(
-                "Unexpected character '{}' at index {}",
-                character, position
-            )),
-            EmptyParentheses => Cow::Borrowed("Empty parentheses"),
+    pub fn span(&self) -> Span {
+        match self {
+            LexError::UnexpectedEOF(span)
+            | LexError::EmptyParentheses(span)
+            | LexError::InexactInteger(span) => *span,
+            LexError::UnexpectedCharacter { position, .. } => Span::at(*position),
         }
     }
 }
@@ -163,52 +376,65 @@ impl From<LexError> for Cow<'static, str> {
 trait TokenBuilder: ::std::fmt::Debug {
     fn can_insert(&self, c: char) -> bool;
     fn push(&mut self, c: char) -> Result<(), ()>;
-    fn into_token(self: Box<Self>) -> Result<Token, LexError>;
+    fn into_token(self: Box<Self>, span: Span) -> Result<TokenKind, LexError>;
 }
 
 #[derive(Debug)]
 struct OperatorBuilder {
-    inner: Option<Operator>,
+    inner: String,
 }
 
 impl OperatorBuilder {
     fn new() -> Self {
-        Self { inner: None }
+        Self {
+            inner: String::new(),
+        }
     }
 }
 
 impl TokenBuilder for OperatorBuilder {
     fn can_insert(&self, c: char) -> bool {
-        self.inner.is_none() && Operator::is_operator(c)
+        let mut symbol = self.inner.clone();
+        symbol.push(c);
+        OperatorInfo::is_symbol_prefix(&symbol)
     }
 
     fn push(&mut self, c: char) -> Result<(), ()> {
-        Operator::from_char(c)
-            .filter(|_| self.inner.is_none())
-            .map(|op| {
-                self.inner = Some(op);
-            }).ok_or(())
+        if self.can_insert(c) {
+            self.inner.push(c);
+            Ok(())
+        } else {
+            Err(())
+        }
     }
 
-    fn into_token(self: Box<Self>) -> Result<Token, LexError> {
-        self.inner.map(Token::Op).ok_or(LexError::UnexpectedEOF)
+    fn into_token(self: Box<Self>, span: Span) -> Result<TokenKind, LexError> {
+        OPERATORS
+            .iter()
+            .find(|info| info.symbol == self.inner)
+            .map(|info| TokenKind::Op(info.symbol))
+            .ok_or(LexError::UnexpectedEOF(span))
     }
 }
 
 #[derive(Debug)]
-struct NegationBuilder {
+struct SymbolBuilder {
+    symbol: char,
     complete: bool,
 }
 
-impl NegationBuilder {
-    fn new() -> Self {
-        Self { complete: false }
+impl SymbolBuilder {
+    fn new(symbol: char) -> Self {
+        Self {
+            symbol,
+            complete: false,
+        }
     }
 }
 
-impl TokenBuilder for NegationBuilder {
+impl TokenBuilder for SymbolBuilder {
     fn can_insert(&self, c: char) -> bool {
-        !self.complete && c == '-'
+        !self.complete && c == self.symbol
     }
 
     fn push(&mut self, c: char) -> Result<(), ()> {
@@ -220,10 +446,16 @@ impl TokenBuilder for NegationBuilder {
         }
     }
 
-    fn into_token(self: Box<Self>) -> Result<Token, LexError> {
-        Some(Token::Negation)
-            .filter(|_| self.complete)
-            .ok_or(LexError::UnexpectedEOF)
+    fn into_token(self: Box<Self>, span: Span) -> Result<TokenKind, LexError> {
+        match self.symbol {
+            '(' => Some(TokenKind::OpenParen),
+            ')' => Some(TokenKind::CloseParen),
+            '=' => Some(TokenKind::Assign),
+            ',' => Some(TokenKind::Comma),
+            '°' => Some(TokenKind::Degree),
+            _ => None,
+        }.filter(|_| self.complete)
+        .ok_or(LexError::UnexpectedEOF(span))
     }
 }
 
@@ -244,7 +476,7 @@ impl NumberBuilder {
 
 impl TokenBuilder for NumberBuilder {
     fn can_insert(&self, c: char) -> bool {
-        c.is_digit(10)
+        c.is_ascii_digit()
             || c == '-' && self.ind == 2 && self.parts[self.ind].is_empty()
             || c == '+' && self.ind == 2 && self.parts[self.ind].is_empty()
             || c == '.' && self.ind == 0 && !self.parts[self.ind].is_empty()
@@ -252,21 +484,21 @@ impl TokenBuilder for NumberBuilder {
                 && self.ind < 2
                 && self.parts[self.ind]
                     .chars()
-                    .filter(|c| c.is_digit(10))
+                    .filter(|c| c.is_ascii_digit())
                     .count()
                     > 0
     }
 
     fn push(&mut self, c: char) -> Result<(), ()> {
         match c {
-            '0'...'9' => self.parts[self.ind].push(c),
+            '0'..='9' => self.parts[self.ind].push(c),
             '-' if self.ind == 2 && self.parts[self.ind].is_empty() => self.parts[self.ind].push(c),
             '+' if self.ind == 2 && self.parts[self.ind].is_empty() => self.parts[self.ind].push(c),
             '.' if self.ind == 0 && !self.parts[self.ind].is_empty() => self.ind += 1,
             'E' if self.ind < 2
                 && self.parts[self.ind]
                     .chars()
-                    .filter(|c| c.is_digit(10))
+                    .filter(|c| c.is_ascii_digit())
                     .count()
                     > 0 =>
             {
@@ -277,7 +509,7 @@ impl TokenBuilder for NumberBuilder {
         Ok(())
     }
 
-    fn into_token(self: Box<Self>) -> Result<Token, LexError> {
+    fn into_token(self: Box<Self>, span: Span) -> Result<TokenKind, LexError> {
         let inchars = [None, Some('.'), Some('E')];
 
         let processed_parts = (0..self.parts.len())
@@ -294,89 +526,75 @@ impl TokenBuilder for NumberBuilder {
             }).collect::<Vec<Option<String>>>();
 
         if processed_parts.iter().any(|o| o.is_none()) {
-            Err(LexError::UnexpectedEOF)
+            Err(LexError::UnexpectedEOF(span))
         } else {
             processed_parts
                 .into_iter()
                 .flatten()
                 .collect::<String>()
                 .parse()
-                .map(Token::Number)
-                .map_err(|_| LexError::UnexpectedEOF)
+                .map(TokenKind::Number)
+                .map_err(|_| LexError::UnexpectedEOF(span))
         }
     }
 }
 
 #[derive(Debug)]
-struct ParenthesesBuilder {
-    inner: String,
-    level: usize,
-    complete: bool,
-    start: usize,
+struct RadixBuilder {
+    prefix: String,
+    radix: Option<u32>,
+    digits: String,
 }
 
-impl ParenthesesBuilder {
-    fn new(start: usize) -> Self {
+impl RadixBuilder {
+    fn new() -> Self {
         Self {
-            inner: String::new(),
-            level: 0,
-            complete: false,
-            start,
+            prefix: String::new(),
+            radix: None,
+            digits: String::new(),
+        }
+    }
+
+    fn radix_of(c: char) -> Option<u32> {
+        match c {
+            'x' => Some(16),
+            'o' => Some(8),
+            'b' => Some(2),
+            _ => None,
         }
     }
 }
 
-impl TokenBuilder for ParenthesesBuilder {
+impl TokenBuilder for RadixBuilder {
     fn can_insert(&self, c: char) -> bool {
-        !self.complete && (c != ')' || self.level > 0)
+        match self.radix {
+            Some(radix) => c.is_digit(radix),
+            None if self.prefix.is_empty() => c == '0',
+            None => Self::radix_of(c).is_some(),
+        }
     }
 
     fn push(&mut self, c: char) -> Result<(), ()> {
-        match c {
-            '(' | ')' if !self.complete => {
-                if c == ')' {
-                    self.level = self.level.checked_sub(1).ok_or(())?;
-                }
-
-                if self.level > 0 {
-                    self.inner.push(c)
-                }
-
-                if c == '(' {
-                    self.level += 1;
-                }
-
-                self.complete = self.level == 0;
-
-                Ok(())
-            }
-            _ if !self.complete => {
-                self.inner.push(c);
-                Ok(())
+        if !self.can_insert(c) {
+            return Err(());
+        }
+        match self.radix {
+            Some(_) => self.digits.push(c),
+            None => {
+                self.radix = Self::radix_of(c);
+                self.prefix.push(c);
             }
-            _ => Err(()),
         }
+        Ok(())
     }
 
-    fn into_token(self: Box<Self>) -> Result<Token, LexError> {
-        if self.inner.is_empty() {
-            Err(LexError::EmptyParentheses)
-        } else if self.complete {
-            lex_ind(&self.inner, self.start)
-                .map(Token::Parentheses)
-                .map_err(|err| {
-                    if let LexError::UnexpectedEOF = err {
-                        LexError::UnexpectedCharacter {
-                            character: ')',
-                            position: self.inner.len() + self.start + 1,
-                        }
-                    } else {
-                        err
-                    }
-                })
-        } else {
-            Err(LexError::UnexpectedEOF)
-        }
+    fn into_token(self: Box<Self>, span: Span) -> Result<TokenKind, LexError> {
+        let radix = self.radix.ok_or(LexError::UnexpectedEOF(span))?;
+        u64::from_str_radix(&self.digits, radix)
+            .ok()
+            .filter(|value| super::is_exact(i128::from(*value)))
+            .map(|value| TokenKind::Number(value as f64))
+            .ok_or(LexError::InexactInteger(span))
     }
 }
 
@@ -395,54 +613,86 @@ impl VariableBuilder {
 
 impl TokenBuilder for VariableBuilder {
     fn can_insert(&self, c: char) -> bool {
-        c.is_ascii_lowercase() || c == '_'
+        let has_digits = self.inner.chars().any(|c| c.is_ascii_digit());
+        match c {
+            '0'..='9' => !self.inner.is_empty(),
+            'a'..='z' | '_' => !has_digits,
+            'A'..='Z' => !has_digits && !self.inner.is_empty(),
+            _ => false,
+        }
     }
 
     fn push(&mut self, c: char) -> Result<(), ()> {
-        match c {
-            'a'...'z' | '_' => self.inner.push(c),
-            _ => return Err(()),
+        if self.can_insert(c) {
+            self.inner.push(c);
+            Ok(())
+        } else {
+            Err(())
         }
-        Ok(())
     }
 
-    fn into_token(self: Box<Self>) -> Result<Token, LexError> {
+    fn into_token(self: Box<Self>, span: Span) -> Result<TokenKind, LexError> {
+        if let Some(symbol) = OperatorInfo::find_word(&self.inner) {
+            return Ok(TokenKind::Op(symbol));
+        }
         let not_empty = !self.inner.is_empty();
         Some(self.inner)
             .filter(|_| not_empty)
-            .map(Token::Var)
-            .ok_or(LexError::UnexpectedEOF)
+            .map(TokenKind::Var)
+            .ok_or(LexError::UnexpectedEOF(span))
     }
 }
 
-fn lex_ind(s: &str, mut ind: usize) -> Result<Vec<Token>, LexError> {
+pub fn lex(s: &str) -> Result<Vec<Token>, LexError> {
     use self::LexError::*;
 
     let mut tokens: Vec<Token> = vec![];
-    let mut pending_num: Option<Box<TokenBuilder>> = None;
-    let mut chars = s.chars().peekable();
+    let mut open_parens: Vec<usize> = vec![];
+    let mut pending_num: Option<Box<dyn TokenBuilder>> = None;
+    let mut token_start = 0;
+    let mut chars = s.chars().enumerate().peekable();
 
-    while let Some(c) = chars.next() {
+    while let Some((ind, c)) = chars.next() {
         if pending_num.is_none() {
-            let last_is_op = tokens
-                .iter()
-                .rev()
-                .skip_while(|t| t.is_neg())
-                .next()
-                .filter(|t| t.is_op())
-                .is_some();
+            token_start = ind;
+            let operand_expected = tokens.last().map(Token::expects_operand).unwrap_or(true);
             let last_is_num = tokens.last().filter(|t| t.is_num()).is_some();
+            let last_is_open = tokens.last().filter(|t| t.is_open_paren()).is_some();
+            let radix_literal = {
+                let mut ahead = chars.clone().map(|(_, ch)| ch);
+                ahead
+                    .next()
+                    .and_then(RadixBuilder::radix_of)
+                    .and_then(|radix| ahead.next().filter(|ch| ch.is_digit(radix)))
+                    .is_some()
+            };
             pending_num = match c {
                 _ if c.is_whitespace() => None,
-                '-' if (last_is_op || tokens.is_empty()) && !last_is_num => {
-                    Some(Box::new(NegationBuilder::new()))
+                '0' if !last_is_num && radix_literal => Some(Box::new(RadixBuilder::new())),
+                '0'..='9' if !last_is_num => Some(Box::new(NumberBuilder::new())),
+                '=' if chars.peek().map(|&(_, ch)| ch) != Some('=') => {
+                    Some(Box::new(SymbolBuilder::new('=')))
                 }
-                '0'...'9' if !last_is_num => Some(Box::new(NumberBuilder::new())),
-                _ if Operator::is_operator(c) && !last_is_op => {
+                _ if OperatorInfo::is_symbol_prefix(&c.to_string())
+                    && (!operand_expected || OperatorInfo::is_prefix_start(c)) =>
+                {
                     Some(Box::new(OperatorBuilder::new()))
                 }
-                '(' => Some(Box::new(ParenthesesBuilder::new(ind))),
-                'a'...'z' | '_' => Some(Box::new(VariableBuilder::new())),
+                '(' => {
+                    open_parens.push(ind);
+                    Some(Box::new(SymbolBuilder::new('(')))
+                }
+                ')' if last_is_open => {
+                    let start = open_parens.last().cloned().unwrap_or(ind);
+                    return Err(EmptyParentheses(Span::new(start, ind + 1)));
+                }
+                ')' if !operand_expected && !open_parens.is_empty() => {
+                    open_parens.pop();
+                    Some(Box::new(SymbolBuilder::new(')')))
+                }
+                ',' if !operand_expected => Some(Box::new(SymbolBuilder::new(','))),
+                '°' if !operand_expected => Some(Box::new(SymbolBuilder::new('°'))),
+                'a'..='z' | '_' => Some(Box::new(VariableBuilder::new())),
                 _ => {
                     return Err(UnexpectedCharacter {
                         character: c,
@@ -458,30 +708,27 @@ fn lex_ind(s: &str, mut ind: usize) -> Result<Vec<Token>, LexError> {
                 position: ind,
             })?;
 
-            let next = chars.peek();
+            let next = chars.peek().map(|&(_, ch)| ch);
             match next {
-                Some(ch) if item.can_insert(*ch) => pending_num = Some(item),
-                _ => tokens.push(item.into_token().map_err(|e| {
-                    next.filter(|_| e.is_eof())
-                        .map(|c| UnexpectedCharacter {
-                            character: *c,
-                            position: ind + 1,
-                        }).unwrap_or(e)
-                })?),
+                Some(ch) if item.can_insert(ch) => pending_num = Some(item),
+                _ => {
+                    let span = Span::new(token_start, ind + 1);
+                    let kind = item.into_token(span).map_err(|e| {
+                        next.filter(|_| e.is_eof())
+                            .map(|character| UnexpectedCharacter {
+                                character,
+                                position: ind + 1,
+                            }).unwrap_or(e)
+                    })?;
+                    tokens.push(Token::new(kind, span));
+                }
             }
         }
-
-        ind += 1;
     }
 
-    Some(tokens)
-        .filter(|toks| {
-            toks.last()
-                .filter(|tok| tok.is_op() || tok.is_neg())
-                .is_none()
-        }).ok_or(UnexpectedEOF)
-}
-
-pub fn lex(s: &str) -> Result<Vec<Token>, LexError> {
-    lex_ind(s, 0)
+    match (tokens.last(), open_parens.last()) {
+        (Some(tok), _) if tok.expects_operand() => Err(UnexpectedEOF(tok.span)),
+        (_, Some(start)) => Err(UnexpectedEOF(Span::new(*start, s.chars().count()))),
+        _ => Ok(tokens),
+    }
 }
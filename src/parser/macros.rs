@@ -12,26 +12,66 @@ macro_rules! simple_enum {
     };
 }
 
+macro_rules! operator_table {
+    ($name:ident { $( $symbol:expr => $op:ident : $fixity:ident($prec:ident, $assoc:ident) ),* }) => {
+        pub const $name: &[OperatorInfo] = &[
+            $(
+                OperatorInfo {
+                    symbol: $symbol,
+                    op: Operator::$op,
+                    fixity: Fixity::$fixity,
+                    precedence: OperatorPrecedence::$prec,
+                    associativity: Associativity::$assoc,
+                },
+            )*
+        ];
+    };
+}
+
 macro_rules! internal_mac_var {
     ({ $map:ident } { $i:expr } $v:expr) => {
         $map.insert($i, VariableValue::Constant($v));
     };
-    ({ $map:ident } { $i:expr } fn($float:ident, $mode:ident) $b:block) => {{
-        fn apply($float: f64, $mode: AngleMode) -> f64 $b
-        $map.insert($i, VariableValue::Function(Box::new(|f, m| apply(f, m))));
+    ({ $map:ident } { $i:expr } fn(rad ! $float:ident) $b:block) => {{
+        fn apply($float: f64) -> Result<f64, EvalError> $b
+
+        fn rad_apply(args: &[f64], mode: AngleMode) -> Result<f64, EvalError> {
+            apply(mode.to_radians(args[0]))
+        }
+
+        $map.insert($i, VariableValue::Function(Arity::Fixed(1), Box::new(rad_apply)));
     }};
-    ({ $map:ident } { $i:expr } fn($float:ident) $b:block) => {{
-        fn apply($float: f64) -> f64 $b
-        $map.insert($i, VariableValue::Function(Box::new(|f, _| apply(f))));
+    ({ $map:ident } { $i:expr } fn([$args:ident; $arity:expr]) $b:block) => {{
+        fn apply($args: &[f64]) -> Result<f64, EvalError> $b
+        $map.insert($i, VariableValue::Function($arity, Box::new(|args, _| apply(args))));
     }};
-    ({ $map:ident } { $i:expr } fn(rad ! $float:ident) $b:block) => {{
-        fn apply($float: f64) -> f64 $b
+    ({ $map:ident } { $i:expr } fn($($float:ident),+ ; $mode:ident) $b:block) => {{
+        fn apply($($float: f64,)+ $mode: AngleMode) -> Result<f64, EvalError> $b
+
+        fn unpack(args: &[f64], mode: AngleMode) -> Result<f64, EvalError> {
+            match args {
+                [$($float),+] => apply($(*$float,)+ mode),
+                // Calls are checked against the fixed arity before they get here
+                _ => unreachable!("{} called with {} arguments", $i, args.len()),
+            }
+        }
+
+        let arity = Arity::Fixed([$(stringify!($float)),+].len());
+        $map.insert($i, VariableValue::Function(arity, Box::new(unpack)));
+    }};
+    ({ $map:ident } { $i:expr } fn($($float:ident),+) $b:block) => {{
+        fn apply($($float: f64),+) -> Result<f64, EvalError> $b
 
-        fn rad_apply(arg: f64, mode: AngleMode) -> f64 {
-            apply(if mode.is_deg() { arg.to_radians() } else { arg })
+        fn unpack(args: &[f64], _: AngleMode) -> Result<f64, EvalError> {
+            match args {
+                [$($float),+] => apply($(*$float),+),
+                // Calls are checked against the fixed arity before they get here
+                _ => unreachable!("{} called with {} arguments", $i, args.len()),
+            }
         }
 
-        $map.insert($i, VariableValue::Function(Box::new(|f, m| rad_apply(f, m))));
+        let arity = Arity::Fixed([$(stringify!($float)),+].len());
+        $map.insert($i, VariableValue::Function(arity, Box::new(unpack)));
     }};
 }
 
@@ -46,3 +86,21 @@ macro_rules! var_map {
         }
     };
 }
+
+macro_rules! complex_map {
+    ( $( $name:ident($mode:ident) { $( $args:pat $(if $guard:expr)? => $body:expr ),+ } ),* ) => {
+        {
+            let mut map: HashMap<&'static str, ComplexFunction> = HashMap::new();
+            $(
+                map.insert(
+                    stringify!($name),
+                    Box::new(|args: &[Complex64], $mode: AngleMode| match args {
+                        $( $args $(if $guard)? => Some($body), )+
+                        _ => None,
+                    }),
+                );
+            )*
+            map
+        }
+    };
+}
@@ -1,11 +1,30 @@
-use std::borrow::Cow;
+use num_complex::Complex64;
+use num_rational::BigRational;
 use std::collections::HashMap;
 use std::fmt::{self, Debug};
 
 #[macro_use]
 mod macros;
 mod ast;
+mod backend;
+mod bound;
+mod compile;
+mod complex;
+mod diff;
+mod env;
+mod error;
+mod exact;
 pub mod lex;
+mod simplify;
+
+pub use self::ast::{EvalError, ExprKind, Expression};
+pub use self::backend::{Backend, BackendKind, FloatBackend, Value};
+pub use self::compile::Compiled;
+pub use self::complex::{format_complex, ComplexBackend, ComplexFormat};
+pub use self::env::Environment;
+pub use self::error::CalcError;
+pub use self::exact::{DecimalBackend, RationalBackend, DEFAULT_PRECISION};
+pub use self::lex::Span;
 
 pub type VarMap = HashMap<&'static str, VariableValue>;
 
@@ -15,91 +34,223 @@ lazy_static! {
         e => { ::std::f64::consts::E },
         sin => {
             fn(rad! x) {
-                x.sin()
+                Ok(x.sin())
             }
         },
         cos => {
             fn(rad! x) {
-                x.cos()
+                Ok(x.cos())
             }
         },
         tan => {
             fn(rad! x) {
-                x.tan()
+                Ok(x.tan())
             }
         },
         asin => {
-            fn(x, mode) {
-                let v = x.asin();
-                if mode.is_deg() {
-                    v.to_degrees()
-                } else { v }
+            fn(x; mode) {
+                let v = domain("asin", x, |x| x.abs() <= 1.0)?.asin();
+                Ok(mode.from_radians(v))
             }
         },
         acos => {
-            fn(x, mode) {
-                let v = x.acos();
-                if mode.is_deg() {
-                    v.to_degrees()
-                } else { v }
+            fn(x; mode) {
+                let v = domain("acos", x, |x| x.abs() <= 1.0)?.acos();
+                Ok(mode.from_radians(v))
             }
         },
         atan => {
-            fn(x, mode) {
+            fn(x; mode) {
                 let v = x.atan();
-                if mode.is_deg() {
-                    v.to_degrees()
-                } else { v }
+                Ok(mode.from_radians(v))
+            }
+        },
+        atan2 => {
+            fn(y, x; mode) {
+                let v = y.atan2(x);
+                Ok(mode.from_radians(v))
+            }
+        },
+        hypot => {
+            fn(x, y) {
+                Ok(x.hypot(y))
+            }
+        },
+        min => {
+            fn([args; Arity::Variadic(1)]) {
+                Ok(args.iter().cloned().fold(f64::INFINITY, f64::min))
+            }
+        },
+        max => {
+            fn([args; Arity::Variadic(1)]) {
+                Ok(args.iter().cloned().fold(f64::NEG_INFINITY, f64::max))
+            }
+        },
+        nCr => {
+            fn(n, r) {
+                let n = domain("nCr", n, |n| n.fract() == 0.0 && n >= 0.0)?;
+                let r = domain("nCr", r, |r| r.fract() == 0.0 && r >= 0.0)?;
+                if r > n {
+                    Ok(0.0)
+                } else {
+                    let r = r.min(n - r);
+                    let value = (1..=r as u64).fold(1.0, |acc, i| acc * (n - r + i as f64) / i as f64);
+                    Ok(value.round())
+                }
+            }
+        },
+        nPr => {
+            fn(n, r) {
+                let n = domain("nPr", n, |n| n.fract() == 0.0 && n >= 0.0)?;
+                let r = domain("nPr", r, |r| r.fract() == 0.0 && r >= 0.0)?;
+                if r > n {
+                    Ok(0.0)
+                } else {
+                    Ok((0..r as u64).fold(1.0, |acc, i| acc * (n - i as f64)))
+                }
+            }
+        },
+        root => {
+            fn(x, n) {
+                if x < 0.0 && n.fract() == 0.0 && n % 2.0 != 0.0 {
+                    Ok(-(-x).powf(1.0 / n))
+                } else {
+                    Ok(domain("root", x, |x| x >= 0.0)?.powf(1.0 / n))
+                }
             }
         },
         ceil => {
             fn(x) {
-                x.ceil()
+                Ok(x.ceil())
             }
         },
         floor => {
             fn(x) {
-                x.floor()
+                Ok(x.floor())
             }
         },
         round => {
             fn(x) {
-                x.round()
+                Ok(x.round())
             }
         },
         ln => {
             fn(x) {
-                x.ln()
+                Ok(domain("ln", x, |x| x > 0.0)?.ln())
             }
         },
         log => {
-            fn(x) {
-                x.log10()
+            fn([args; Arity::Range(1, 2)]) {
+                match args.get(1) {
+                    Some(&base) => {
+                        let base = domain("log", base, |b| b > 0.0 && b != 1.0)?;
+                        Ok(domain("log", args[0], |x| x > 0.0)?.log(base))
+                    }
+                    None => Ok(domain("log", args[0], |x| x > 0.0)?.log10()),
+                }
             }
         },
         abs => {
             fn(x) {
-                x.abs()
+                Ok(x.abs())
             }
         },
         sqrt => {
             fn(x) {
-                x.sqrt()
+                Ok(domain("sqrt", x, |x| x >= 0.0)?.sqrt())
+            }
+        },
+        gamma => {
+            fn(x) {
+                Ok(gamma(domain("gamma", x, |x| x > 0.0 || x.fract() != 0.0)?))
+            }
+        },
+        polygamma => {
+            fn(n, x) {
+                let n = domain("polygamma", n, |n| n.fract() == 0.0 && n >= 0.0)?;
+                let x = domain("polygamma", x, |x| x > 0.0 || x.fract() != 0.0)?;
+                Ok(polygamma(n as u32, x))
             }
         }
     };
 }
 
+fn domain<F>(func: &str, value: f64, valid: F) -> Result<f64, EvalError>
+where
+    F: Fn(f64) -> bool,
+{
+    if valid(value) {
+        Ok(value)
+    } else {
+        Err(EvalError::Domain(func.to_string(), value))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Arity {
+    Fixed(usize),
+    Range(usize, usize),
+    Variadic(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Fixed(n) => count == n,
+            Arity::Range(min, max) => min <= count && count <= max,
+            Arity::Variadic(min) => min <= count,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (desc, last) = match *self {
+            Arity::Fixed(n) => (n.to_string(), n),
+            Arity::Range(min, max) => (format!("{} to {}", min, max), max),
+            Arity::Variadic(min) => (format!("at least {}", min), min),
+        };
+        write!(f, "{} argument{}", desc, if last == 1 { "" } else { "s" })
+    }
+}
+
+pub type Builtin = Box<dyn Fn(&[f64], AngleMode) -> Result<f64, EvalError> + Send + Sync>;
+
 pub enum VariableValue {
     Constant(f64),
-    Function(Box<Fn(f64, AngleMode) -> f64 + Send + Sync>),
+    Complex(Complex64),
+    Rational(BigRational),
+    Function(Arity, Builtin),
+    UserFunction {
+        params: Vec<String>,
+        body: ast::Expression,
+    },
+}
+
+impl VariableValue {
+    pub fn arity(&self) -> Option<Arity> {
+        match self {
+            VariableValue::Constant(_) | VariableValue::Complex(_) | VariableValue::Rational(_) => {
+                None
+            }
+            VariableValue::Function(arity, _) => Some(*arity),
+            VariableValue::UserFunction { params, .. } => Some(Arity::Fixed(params.len())),
+        }
+    }
 }
 
 impl Debug for VariableValue {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             VariableValue::Constant(num) => write!(f, "VariableValue::Constant({})", num),
-            VariableValue::Function(_) => write!(f, "VariableValue::Function"),
+            VariableValue::Complex(num) => write!(f, "VariableValue::Complex({})", num),
+            VariableValue::Rational(num) => write!(f, "VariableValue::Rational({})", num),
+            VariableValue::Function(arity, _) => {
+                write!(f, "VariableValue::Function({:?})", arity)
+            }
+            VariableValue::UserFunction { params, .. } => {
+                write!(f, "VariableValue::UserFunction({})", params.join(", "))
+            }
         }
     }
 }
@@ -108,44 +259,427 @@ impl Debug for VariableValue {
 pub enum AngleMode {
     Deg,
     Rad,
+    Grad,
 }
 
 impl AngleMode {
     pub fn is_deg(&self) -> bool {
         *self == AngleMode::Deg
     }
+
+    pub fn to_radians(&self, value: f64) -> f64 {
+        match self {
+            AngleMode::Deg => value.to_radians(),
+            AngleMode::Rad => value,
+            AngleMode::Grad => value * ::std::f64::consts::PI / 200.0,
+        }
+    }
+
+    pub fn from_radians(&self, value: f64) -> f64 {
+        match self {
+            AngleMode::Deg => value.to_degrees(),
+            AngleMode::Rad => value,
+            AngleMode::Grad => value * 200.0 / ::std::f64::consts::PI,
+        }
+    }
+
+    // Factor that converts a value in this unit to the given mode
+    pub fn factor(&self, mode: AngleMode) -> f64 {
+        mode.from_radians(self.to_radians(1.0))
+    }
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            AngleMode::Deg => "°",
+            AngleMode::Rad => "rad",
+            AngleMode::Grad => "grad",
+        }
+    }
+
+    pub fn from_suffix(suffix: &str) -> Option<Self> {
+        [AngleMode::Deg, AngleMode::Rad, AngleMode::Grad]
+            .iter()
+            .find(|mode| mode.suffix() == suffix)
+            .cloned()
+    }
+
+    pub fn cycle(&self) -> Self {
+        match self {
+            AngleMode::Rad => AngleMode::Deg,
+            AngleMode::Deg => AngleMode::Grad,
+            AngleMode::Grad => AngleMode::Rad,
+        }
+    }
 }
 
-impl ToString for AngleMode {
-    fn to_string(&self) -> String {
+impl fmt::Display for AngleMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            AngleMode::Deg => "Deg",
-            AngleMode::Rad => "Rad",
-        }.to_string()
+            AngleMode::Deg => write!(f, "Deg"),
+            AngleMode::Rad => write!(f, "Rad"),
+            AngleMode::Grad => write!(f, "Grad"),
+        }
     }
 }
 
-impl ::std::ops::Not for AngleMode {
-    type Output = AngleMode;
+simple_enum! {
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    WordSize {
+        Bits8,
+        Bits16,
+        Bits32,
+        Bits64
+    }
+}
 
-    fn not(self) -> Self {
+impl WordSize {
+    pub fn bits(&self) -> u32 {
         match self {
-            AngleMode::Deg => AngleMode::Rad,
-            AngleMode::Rad => AngleMode::Deg,
+            WordSize::Bits8 => 8,
+            WordSize::Bits16 => 16,
+            WordSize::Bits32 => 32,
+            WordSize::Bits64 => 64,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct IntegerMode {
+    pub size: WordSize,
+    pub signed: bool,
+}
+
+impl IntegerMode {
+    pub fn new(size: WordSize, signed: bool) -> Self {
+        Self { size, signed }
+    }
+
+    pub fn wrap(&self, value: i128) -> i128 {
+        let modulus = 1i128 << self.size.bits();
+        let value = value.rem_euclid(modulus);
+        if self.signed && value >= modulus / 2 {
+            value - modulus
+        } else {
+            value
+        }
+    }
+
+    pub fn to_integer(&self, value: f64) -> Option<i128> {
+        Some(value)
+            .filter(|v| v.is_finite() && v.fract() == 0.0)
+            .map(|v| self.wrap(v as i128))
+    }
+
+    pub fn cycle(&self) -> Self {
+        let sizes = WordSize::VALUES;
+        let index = sizes.iter().position(|s| *s == self.size).unwrap_or(0);
+        match sizes.get(index + 1) {
+            Some(size) => Self::new(*size, self.signed),
+            None => Self::new(sizes[0], !self.signed),
         }
     }
 }
 
-pub fn eval_math(s: &str, mode: AngleMode) -> Result<f64, Cow<'static, str>> {
-    ast::ast_gen(lex::lex(s)?, &DEFAULT_VARS)?
-        .get_value(mode, &DEFAULT_VARS)
+// Whether an integer survives the round trip through f64
+fn is_exact(value: i128) -> bool {
+    let value = value.unsigned_abs();
+    value == 0 || 128 - value.leading_zeros() - value.trailing_zeros() <= f64::MANTISSA_DIGITS
+}
+
+impl Default for IntegerMode {
+    fn default() -> Self {
+        Self::new(WordSize::Bits64, true)
+    }
+}
+
+impl fmt::Display for IntegerMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.signed { "i" } else { "u" };
+        write!(f, "{}{}", sign, self.size.bits())
+    }
+}
+
+fn run<B: Backend>(
+    backend: &B,
+    s: &str,
+    mode: AngleMode,
+    env: &mut Environment,
+) -> Result<Option<B::Value>, CalcError> {
+    ast::statement_gen(lex::lex(s)?, env)?
+        .execute(backend, mode, env)
         .map_err(|e| e.into())
 }
 
+pub fn eval_math(
+    s: &str,
+    mode: AngleMode,
+    env: &mut Environment,
+) -> Result<Option<f64>, CalcError> {
+    run(&FloatBackend, s, mode, env)
+}
+
+pub fn eval_complex(
+    s: &str,
+    mode: AngleMode,
+    env: &mut Environment,
+) -> Result<Option<Complex64>, CalcError> {
+    run(&ComplexBackend, s, mode, env)
+}
+
+pub fn evaluate(
+    s: &str,
+    mode: AngleMode,
+    env: &mut Environment,
+) -> Result<Option<Value>, CalcError> {
+    match env.backend() {
+        BackendKind::Float => run(&FloatBackend, s, mode, env).map(|v| v.map(Value::Real)),
+        BackendKind::Complex => run(&ComplexBackend, s, mode, env).map(|v| v.map(Value::Complex)),
+        BackendKind::Rational => {
+            run(&RationalBackend, s, mode, env).map(|v| v.map(Value::Rational))
+        }
+        BackendKind::Decimal(precision) => {
+            run(&DecimalBackend::new(precision), s, mode, env).map(|v| v.map(Value::Decimal))
+        }
+    }
+}
+
+fn simplified<B: Backend>(
+    backend: &B,
+    s: &str,
+    mode: AngleMode,
+    env: &Environment,
+) -> Result<String, CalcError> {
+    let statement = ast::statement_gen(lex::lex(s)?, env)?;
+    Ok(statement.simplify(backend, mode, env).to_string())
+}
+
+pub fn simplify(s: &str, mode: AngleMode, env: &Environment) -> Result<String, CalcError> {
+    match env.backend() {
+        BackendKind::Float => simplified(&FloatBackend, s, mode, env),
+        BackendKind::Complex => simplified(&ComplexBackend, s, mode, env),
+        BackendKind::Rational => simplified(&RationalBackend, s, mode, env),
+        BackendKind::Decimal(precision) => {
+            simplified(&DecimalBackend::new(precision), s, mode, env)
+        }
+    }
+}
+
+pub fn parse_expression(
+    s: &str,
+    params: &[String],
+    env: &Environment,
+) -> Result<Expression, CalcError> {
+    Ok(ast::expression_gen(lex::lex(s)?, params, env)?)
+}
+
+pub fn compile(
+    s: &str,
+    vars: &[String],
+    mode: AngleMode,
+    env: &Environment,
+) -> Result<Compiled, CalcError> {
+    Compiled::new(&parse_expression(s, vars, env)?, vars, mode, env)
+}
+
+pub fn diff(
+    expr: &Expression,
+    var: &str,
+    mode: AngleMode,
+    env: &Environment,
+) -> Result<Expression, CalcError> {
+    let derivative = expr.derivative(var, mode, env)?;
+    Ok(derivative.simplify(&FloatBackend, mode, env))
+}
+
+pub fn deriv(f: &str, x0: f64, mode: AngleMode, env: &Environment) -> Result<f64, CalcError> {
+    const PARAM: &str = "x";
+
+    let span = Span::new(0, f.chars().count());
+    let (param, body) = match env.get(f) {
+        Some(VariableValue::UserFunction { params, body }) if params.len() == 1 => {
+            (params[0].as_str(), body.clone())
+        }
+        Some(VariableValue::Function(arity, _)) if arity.accepts(1) => {
+            let arg = Expression::new(ExprKind::Variable(PARAM.to_string()), span);
+            let kind = ExprKind::CallExpresion {
+                args: vec![arg],
+                func: f.to_string(),
+            };
+            (PARAM, Expression::new(kind, span))
+        }
+        Some(value) => {
+            return Err(match value.arity() {
+                Some(expected) => CalcError::ArgumentCount {
+                    func: f.to_string(),
+                    expected,
+                    found: 1,
+                    span,
+                },
+                None => CalcError::NonFunction(f.to_string(), span),
+            })
+        }
+        None => return Err(CalcError::UndefinedIdent(f.to_string(), span)),
+    };
+
+    let derivative = body.derivative(param, mode, env)?;
+    Ok(derivative.get_value_at(&FloatBackend, mode, env, param, x0)?)
+}
+
+fn equation(s: &str, params: &[String], env: &Environment) -> Result<Expression, CalcError> {
+    let mut tokens = lex::lex(s)?;
+    let equals = match tokens.iter().position(lex::Token::is_assign) {
+        Some(equals) => equals,
+        None => return Ok(ast::expression_gen(tokens, params, env)?),
+    };
+
+    let right = tokens.split_off(equals + 1);
+    tokens.pop();
+    let left = ast::expression_gen(tokens, params, env)?;
+    let right = ast::expression_gen(right, params, env)?;
+    let span = left.span.to(right.span);
+    let kind = ExprKind::Binary {
+        op: lex::Operator::Minus,
+        left: left.into(),
+        right: Expression::new(ExprKind::Paren(right.into()), span).into(),
+    };
+    Ok(Expression::new(kind, span))
+}
+
+pub fn solve_variable(s: &str, env: &Environment) -> Result<String, CalcError> {
+    let mut vars = vec![];
+    loop {
+        match equation(s, &vars, env) {
+            Ok(_) if vars.len() == 1 => return Ok(vars.remove(0)),
+            Ok(expr) => return Err(CalcError::SolveVariables(vars, expr.span)),
+            Err(CalcError::UndefinedIdent(name, _)) if !vars.contains(&name) => vars.push(name),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub fn solve(s: &str, var: &str, mode: AngleMode, env: &Environment) -> Result<f64, CalcError> {
+    let expr = equation(s, &[var.to_string()], env)?;
+    let f = |x| expr.get_value_at(&FloatBackend, mode, env, var, x);
+    Ok(bound::BoundFunc::Solve.apply(f, &[], expr.span, expr.span)?)
+}
+
+// Roots are found from sign changes, so even-multiplicity roots such as that of x^2
+// only turn up when a sample lands on them exactly
+pub fn solve_all(
+    s: &str,
+    var: &str,
+    a: f64,
+    b: f64,
+    mode: AngleMode,
+    env: &Environment,
+) -> Result<Vec<f64>, CalcError> {
+    let expr = equation(s, &[var.to_string()], env)?;
+    let f = |x| expr.get_value_at(&FloatBackend, mode, env, var, x);
+    Ok(bound::roots(f, a, b, expr.span)?)
+}
+
+pub fn integrate(
+    s: &str,
+    var: &str,
+    a: f64,
+    b: f64,
+    mode: AngleMode,
+    env: &Environment,
+) -> Result<(f64, f64), CalcError> {
+    let expr = parse_expression(s, &[var.to_string()], env)?;
+    let f = |x| expr.get_value_at(&FloatBackend, mode, env, var, x);
+    Ok(bound::integrate(f, a, b, expr.span)?)
+}
+
 pub fn to_fixed(f: f64, place: u32) -> f64 {
     let pow_place = 10f64.powi(place as i32);
     (f * pow_place).round() / pow_place
 }
 
+const LANCZOS_G: f64 = 7.0;
+const LANCZOS_COEFFICIENTS: [f64; 9] = [
+    0.999_999_999_999_809_9,
+    676.520_368_121_885_1,
+    -1_259.139_216_722_402_8,
+    771.323_428_777_653_1,
+    -176.615_029_162_140_6,
+    12.507_343_278_686_905,
+    -0.138_571_095_265_720_12,
+    9.984_369_578_019_572e-6,
+    1.505_632_735_149_311_6e-7,
+];
+
+pub fn gamma(x: f64) -> f64 {
+    use std::f64::consts::PI;
+
+    if x.fract() == 0.0 && x <= 0.0 {
+        f64::NAN
+    } else if x.fract() == 0.0 && x <= 172.0 {
+        (2..x as u64).fold(1.0, |acc, i| acc * i as f64)
+    } else if x < 0.5 {
+        PI / ((PI * x).sin() * gamma(1.0 - x))
+    } else {
+        let x = x - 1.0;
+        let t = x + LANCZOS_G + 0.5;
+        let sum = LANCZOS_COEFFICIENTS[1..]
+            .iter()
+            .enumerate()
+            .fold(LANCZOS_COEFFICIENTS[0], |acc, (i, c)| {
+                acc + c / (x + i as f64 + 1.0)
+            });
+        let half_pow = t.powf((x + 0.5) / 2.0);
+        (2.0 * PI).sqrt() * sum * half_pow * (-t).exp() * half_pow
+    }
+}
+
+const POLYGAMMA_SHIFT: f64 = 20.0;
+const BERNOULLI: [f64; 7] = [
+    1.0 / 6.0,
+    -1.0 / 30.0,
+    1.0 / 42.0,
+    -1.0 / 30.0,
+    5.0 / 66.0,
+    -691.0 / 2730.0,
+    7.0 / 6.0,
+];
+
+pub fn polygamma(n: u32, x: f64) -> f64 {
+    if (x.fract() == 0.0 && x <= 0.0) || x < -1E6 {
+        return f64::NAN;
+    }
+
+    let n_f = n as f64;
+    let sign = (-1f64).powi(n as i32 + 1);
+    let mut x = x;
+    let mut shift = 0.0;
+    while x < POLYGAMMA_SHIFT {
+        shift += x.powi(-(n as i32 + 1));
+        x += 1.0;
+    }
+
+    let series = BERNOULLI.iter().enumerate().fold(0.0, |acc, (k, b)| {
+        let k = 2.0 * (k as f64 + 1.0);
+        if n == 0 {
+            acc + b / (k * x.powf(k))
+        } else {
+            acc + b * gamma(k + n_f) / (gamma(k + 1.0) * x.powf(k + n_f))
+        }
+    });
+    let value = if n == 0 {
+        x.ln() - 0.5 / x - series
+    } else {
+        sign * (gamma(n_f) / x.powf(n_f) + gamma(n_f + 1.0) / (2.0 * x.powf(n_f + 1.0)) + series)
+    };
+    value + sign * gamma(n_f + 1.0) * shift
+}
+
+pub fn factorial(x: f64) -> Option<f64> {
+    if x.fract() == 0.0 && x < 0.0 {
+        None
+    } else {
+        Some(gamma(x + 1.0))
+    }
+}
+
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,213 @@
+use super::ast::{ExprKind, Expression, Statement};
+use super::backend::Backend;
+use super::lex::Operator;
+use super::{AngleMode, Environment, VariableValue};
+
+impl Expression {
+    pub fn simplify<B: Backend>(self, backend: &B, mode: AngleMode, context: &Environment) -> Self {
+        use self::ExprKind::*;
+
+        let span = self.span;
+        let simplify = |expr: Box<Expression>| expr.simplify(backend, mode, context);
+        let kind = match self.kind {
+            Binary { op, left, right } => {
+                let left = simplify(left);
+                let right_span = right.span;
+                let right = match right.kind {
+                    Unary {
+                        op: Operator::Percent,
+                        operand,
+                    } if op == Operator::Plus || op == Operator::Minus => Expression::new(
+                        Unary {
+                            op: Operator::Percent,
+                            operand: simplify(operand).into(),
+                        },
+                        right_span,
+                    ),
+                    kind => match simplify(Expression::new(kind, right.span).into()) {
+                        right if is_percent(op, &right) => {
+                            Expression::new(Paren(right.into()), right_span)
+                        }
+                        right => right,
+                    },
+                };
+                match identity(op, &left, &right) {
+                    Some(Side::Left) => return left,
+                    Some(Side::Right) => return right,
+                    None => Binary {
+                        op,
+                        left: left.into(),
+                        right: right.into(),
+                    },
+                }
+            }
+            Unary { op, operand } => match simplify(operand) {
+                Expression {
+                    kind:
+                        Unary {
+                            op: Operator::Neg,
+                            operand,
+                        },
+                    ..
+                } if op == Operator::Neg => return *operand,
+                operand => Unary {
+                    op,
+                    operand: operand.into(),
+                },
+            },
+            Conditional {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let condition = simplify(condition);
+                match condition.kind {
+                    Number(value) if value != 0.0 => return simplify(then),
+                    Number(_) => return simplify(otherwise),
+                    _ => Conditional {
+                        condition: condition.into(),
+                        then: simplify(then).into(),
+                        otherwise: simplify(otherwise).into(),
+                    },
+                }
+            }
+            Paren(inner) => match simplify(inner) {
+                percent @ Expression {
+                    kind:
+                        Unary {
+                            op: Operator::Percent,
+                            ..
+                        },
+                    ..
+                } => Paren(percent.into()),
+                inner => return inner,
+            },
+            CallExpresion { args, func } => CallExpresion {
+                args: args
+                    .into_iter()
+                    .map(|arg| arg.simplify(backend, mode, context))
+                    .collect(),
+                func,
+            },
+            Bound {
+                func,
+                var,
+                body,
+                args,
+            } => Bound {
+                func,
+                var,
+                body: simplify(body).into(),
+                args: args
+                    .into_iter()
+                    .map(|arg| arg.simplify(backend, mode, context))
+                    .collect(),
+            },
+            Angle { unit, operand } => Angle {
+                unit,
+                operand: simplify(operand).into(),
+            },
+            kind => kind,
+        };
+
+        Expression::new(kind, span).fold(backend, mode, context)
+    }
+
+    fn is_literal(&self) -> bool {
+        matches!(
+            self.kind,
+            ExprKind::Number(_) | ExprKind::Complex(_) | ExprKind::Rational(_)
+        )
+    }
+
+    fn is_foldable(&self, context: &Environment) -> bool {
+        use self::ExprKind::*;
+
+        match &self.kind {
+            Binary { op, left, right } => {
+                let right = match &right.kind {
+                    Unary { operand, .. } if is_percent(*op, right) => operand,
+                    _ => right,
+                };
+                left.is_literal() && right.is_literal()
+            }
+            Unary { operand, .. } | Angle { operand, .. } => operand.is_literal(),
+            CallExpresion { args, func } => {
+                let is_builtin = matches!(context.get(func), Some(VariableValue::Function(..)));
+                is_builtin && args.iter().all(Expression::is_literal)
+            }
+            _ => false,
+        }
+    }
+
+    fn fold<B: Backend>(self, backend: &B, mode: AngleMode, context: &Environment) -> Self {
+        if !self.is_foldable(context) {
+            return self;
+        }
+        let kind = match self.get_value(backend, mode, context) {
+            Ok(value) => match backend.to_variable(value) {
+                VariableValue::Constant(value) => ExprKind::Number(value),
+                VariableValue::Complex(value) => ExprKind::Complex(value),
+                VariableValue::Rational(value) => ExprKind::Rational(value),
+                _ => return self,
+            },
+            Err(_) => return self,
+        };
+        Expression::new(kind, self.span)
+    }
+}
+
+enum Side {
+    Left,
+    Right,
+}
+
+fn is_percent(op: Operator, right: &Expression) -> bool {
+    let is_percent = matches!(
+        right.kind,
+        ExprKind::Unary {
+            op: Operator::Percent,
+            ..
+        }
+    );
+    is_percent && (op == Operator::Plus || op == Operator::Minus)
+}
+
+fn identity(op: Operator, left: &Expression, right: &Expression) -> Option<Side> {
+    use self::Operator::*;
+
+    let is = |expr: &Expression, n: f64| matches!(expr.kind, ExprKind::Number(v) if v == n);
+    match op {
+        Plus if is(left, 0.0) && !is_percent(op, right) => Some(Side::Right),
+        Plus | Minus if is(right, 0.0) => Some(Side::Left),
+        Mult if is(left, 1.0) => Some(Side::Right),
+        Mult | Div | Exp if is(right, 1.0) => Some(Side::Left),
+        _ => None,
+    }
+}
+
+impl Statement {
+    pub fn simplify<B: Backend>(self, backend: &B, mode: AngleMode, context: &Environment) -> Self {
+        match self {
+            Statement::Assignment { name, span, value } => Statement::Assignment {
+                name,
+                span,
+                value: value.simplify(backend, mode, context),
+            },
+            Statement::Definition {
+                name,
+                span,
+                params,
+                body,
+            } => Statement::Definition {
+                name,
+                span,
+                params,
+                body: body.simplify(backend, mode, context),
+            },
+            Statement::Expression(expr) => {
+                Statement::Expression(expr.simplify(backend, mode, context))
+            }
+        }
+    }
+}
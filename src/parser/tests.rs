@@ -1,23 +1,33 @@
+#![allow(clippy::approx_constant)]
+
 #[test]
 fn eval_radian_mode() {
-    use super::{eval_math, to_fixed, AngleMode};
+    use super::CalcError::*;
+    use super::{eval_math, lex::TokenKind, to_fixed, AngleMode, Environment, Span};
 
     let fixed = 7u32;
+    let mut env = Environment::new();
 
     let tests = vec![
         ("3 + 3 ^ 2", Ok(12.0)),
-        ("5 + 3 -", Err("Incomplete expression".into())),
+        ("5 + 3 -", Err(IncompleteExpression(Span::new(6, 7)))),
         ("3floor(2.4) + 2", Ok(8.0)),
         ("  1 + 2*   3 ^4+   5 ", Ok(168.0)),
         ("3pi3pi", Ok(88.8264396)),
         ("8E3 / 100 + 30", Ok(110.0)),
         ("sqrt(4E4) / (3 - 1)", Ok(100.0)),
-        ("3 .20", Err("Unexpected character \'.\' at index 2".into())),
+        ("3 .20", Err(UnexpectedCharacter('.', Span::new(2, 3)))),
         ("7 + (3) + 3e2", Ok(26.3096910)),
         ("1 + abs(3 + 2 * -20 - 2) + 3 / 2", Ok(41.5)),
-        ("3 + abs - 2", Err("Unexpected operator -".into())),
-        ("3 + () / 2", Err("Empty parentheses".into())),
-        ("3 + (4 + ((3)) * 3", Err("Incomplete expression".into())),
+        (
+            "3 + abs - 2",
+            Err(UnexpectedToken(TokenKind::Op("-"), Span::new(8, 9))),
+        ),
+        ("3 + () / 2", Err(EmptyParentheses(Span::new(4, 6)))),
+        (
+            "3 + (4 + ((3)) * 3",
+            Err(IncompleteExpression(Span::new(4, 18))),
+        ),
         ("((((((((3))))) + 4))) - 1 * 2", Ok(5.0)),
         ("((5((2 / 3)3))sqrt(4) + 2) ^ 2 + 2sin(pi) ^ 3", Ok(484.0)),
         ("3+-4*2", Ok(-5.0)),
@@ -25,14 +35,1493 @@ fn eval_radian_mode() {
         ("-2^2", Ok(-4.0)),
         ("-2*-2", Ok(4.0)),
         ("-(3 + 2)4", Ok(-20.0)),
-        ("3-*2", Err("Unexpected character \'*\' at index 2".into())),
+        ("3-*2", Err(UnexpectedCharacter('*', Span::new(2, 3)))),
         ("3*--abs(-2)", Ok(6.0)),
     ];
 
     for (expr, result) in tests.into_iter() {
         assert_eq!(
-            eval_math(expr, AngleMode::Rad).map(|f| to_fixed(f, fixed)),
+            eval_math(expr, AngleMode::Rad, &mut env).map(|f| f.map(|f| to_fixed(f, fixed))),
+            result.map(Some)
+        );
+    }
+}
+
+#[test]
+fn eval_assignment() {
+    use super::CalcError::*;
+    use super::{eval_math, lex::TokenKind, AngleMode, Environment, Span};
+
+    let mut env = Environment::new();
+
+    let tests = vec![
+        ("x = 3", Ok(3.0)),
+        ("2x + 1", Ok(7.0)),
+        ("x = x * -2", Ok(-6.0)),
+        ("long_name = 4", Ok(4.0)),
+        ("x long_name", Ok(-24.0)),
+        ("y + 1", Err(UndefinedIdent("y".into(), Span::new(0, 1)))),
+        (
+            "pi = 3",
+            Err(BuiltinAssignment("pi".into(), Span::new(0, 2))),
+        ),
+        (
+            "sin = 3",
+            Err(BuiltinAssignment("sin".into(), Span::new(0, 3))),
+        ),
+        (
+            "3 = x",
+            Err(UnexpectedToken(TokenKind::Assign, Span::new(2, 3))),
+        ),
+        ("x =", Err(IncompleteExpression(Span::new(2, 3)))),
+        ("x2 = 5", Ok(5.0)),
+        ("x2 x3", Ok(-90.0)),
+        ("x", Ok(-6.0)),
+    ];
+
+    for (expr, result) in tests.into_iter() {
+        assert_eq!(eval_math(expr, AngleMode::Rad, &mut env), result.map(Some));
+    }
+}
+
+#[test]
+fn eval_user_functions() {
+    use super::CalcError::*;
+    use super::{eval_math, lex::TokenKind, AngleMode, Environment, Span};
+
+    let mut env = Environment::new();
+
+    let tests = vec![
+        ("f(x) = x^2 + 1", Ok(None)),
+        ("f(3)", Ok(Some(10.0))),
+        ("2f(-2) + 1", Ok(Some(11.0))),
+        ("x = 4", Ok(Some(4.0))),
+        ("g(x) = f(x) - x", Ok(None)),
+        ("g(x + 1)", Ok(Some(21.0))),
+        ("deg(x) = 180x / pi", Ok(None)),
+        ("sin(pi / 6) + deg(pi)", Ok(Some(180.5))),
+        ("h(x) = h(x - 1)", Ok(None)),
+        ("h(1)", Err(RecursionLimit("h".into(), Span::new(7, 15)))),
+        (
+            "sqrt(x) = 2",
+            Err(BuiltinAssignment("sqrt".into(), Span::new(0, 4))),
+        ),
+        (
+            "k(pi) = pi",
+            Err(BuiltinAssignment("pi".into(), Span::new(2, 4))),
+        ),
+        ("k(x) = y", Err(UndefinedIdent("y".into(), Span::new(7, 8)))),
+        (
+            "k(2) = 3",
+            Err(UnexpectedToken(TokenKind::Number(2.0), Span::new(2, 3))),
+        ),
+        ("x f(x) f", Err(UnexpectedEOF(Span::new(7, 8)))),
+        ("a = 2", Ok(Some(2.0))),
+        ("p(x) = a * x", Ok(None)),
+        ("a = 3", Ok(Some(3.0))),
+        ("p(1)", Ok(Some(3.0))),
+        ("a(x) = x", Ok(None)),
+        ("p(1)", Err(UndefinedIdent("a".into(), Span::new(7, 8)))),
+    ];
+
+    for (expr, result) in tests.into_iter() {
+        assert_eq!(
+            eval_math(expr, AngleMode::Rad, &mut env).map(|v| v.map(|f| f.round())),
+            result.map(|v| v.map(f64::round))
+        );
+    }
+}
+
+#[test]
+fn eval_multiple_arguments() {
+    use super::CalcError::*;
+    use super::{eval_math, lex::TokenKind, to_fixed, AngleMode, Arity, Environment, Span};
+
+    let mut env = Environment::new();
+
+    let tests = vec![
+        ("atan2(1, 1)", AngleMode::Deg, Ok(Some(45.0))),
+        ("atan2(-1, -1)", AngleMode::Rad, Ok(Some(-2.3561945))),
+        ("hypot(3, 4)", AngleMode::Rad, Ok(Some(5.0))),
+        ("max(3, -2, 7 - 1, 4)", AngleMode::Rad, Ok(Some(6.0))),
+        ("min(2)", AngleMode::Rad, Ok(Some(2.0))),
+        ("nCr(5, 2) + nPr(5, 2)", AngleMode::Rad, Ok(Some(30.0))),
+        ("nCr(3, 5)", AngleMode::Rad, Ok(Some(0.0))),
+        ("root(27, 3) root(-8, 3)", AngleMode::Rad, Ok(Some(-6.0))),
+        ("log(1000) + log(8, 2)", AngleMode::Rad, Ok(Some(6.0))),
+        (
+            "-max(1, (2, 3))",
+            AngleMode::Rad,
+            Err(UnexpectedToken(TokenKind::Comma, Span::new(10, 11))),
+        ),
+        ("f(x, y) = x^2 + y", AngleMode::Rad, Ok(None)),
+        ("f(3, -1)", AngleMode::Rad, Ok(Some(8.0))),
+        (
+            "f(3)",
+            AngleMode::Rad,
+            Err(ArgumentCount {
+                func: "f".into(),
+                expected: Arity::Fixed(2),
+                found: 1,
+                span: Span::new(0, 4),
+            }),
+        ),
+        (
+            "atan2(1)",
+            AngleMode::Rad,
+            Err(ArgumentCount {
+                func: "atan2".into(),
+                expected: Arity::Fixed(2),
+                found: 1,
+                span: Span::new(0, 8),
+            }),
+        ),
+        (
+            "sin(1, 2)",
+            AngleMode::Rad,
+            Err(ArgumentCount {
+                func: "sin".into(),
+                expected: Arity::Fixed(1),
+                found: 2,
+                span: Span::new(0, 9),
+            }),
+        ),
+        (
+            "log(1, 2, 3)",
+            AngleMode::Rad,
+            Err(ArgumentCount {
+                func: "log".into(),
+                expected: Arity::Range(1, 2),
+                found: 3,
+                span: Span::new(0, 12),
+            }),
+        ),
+        (
+            "max(1,, 2)",
+            AngleMode::Rad,
+            Err(UnexpectedCharacter(',', Span::new(6, 7))),
+        ),
+        (
+            "max(1, 2,)",
+            AngleMode::Rad,
+            Err(UnexpectedCharacter(')', Span::new(9, 10))),
+        ),
+    ];
+
+    for (expr, mode, result) in tests.into_iter() {
+        assert_eq!(
+            eval_math(expr, mode, &mut env).map(|v| v.map(|f| to_fixed(f, 7))),
             result
         );
     }
 }
+
+#[test]
+fn error_spans() {
+    use super::{eval_math, AngleMode, Environment, Span};
+
+    let mut env = Environment::new();
+
+    let tests = vec![
+        ("3 + abs - 2", Span::new(8, 9)),
+        ("5 + 3 -", Span::new(6, 7)),
+        ("3 .20", Span::new(2, 3)),
+        ("3 + () / 2", Span::new(4, 6)),
+        ("3 + (4 + ((3)) * 3", Span::new(4, 18)),
+        ("1 + (2 * foo)", Span::new(9, 12)),
+        ("2 * -atan2(1)", Span::new(5, 13)),
+        ("max(1, (2, 3))", Span::new(9, 10)),
+        ("(1 + 2) 3E", Span::new(8, 10)),
+        ("pi = 2", Span::new(0, 2)),
+        ("f(x, sin) = x", Span::new(5, 8)),
+        ("", Span::new(0, 0)),
+        ("2 + (-3)!", Span::new(4, 9)),
+    ];
+
+    for (expr, span) in tests.into_iter() {
+        assert_eq!(
+            eval_math(expr, AngleMode::Rad, &mut env).map_err(|e| e.span()),
+            Err(span),
+            "{}",
+            expr
+        );
+    }
+}
+
+#[test]
+fn eval_precedence() {
+    use super::CalcError::*;
+    use super::{eval_math, to_fixed, AngleMode, Environment, Span};
+
+    let mut env = Environment::new();
+
+    let tests = vec![
+        ("2^3^2", Ok(512.0)),
+        ("(2^3)^2", Ok(64.0)),
+        ("2^-1", Ok(0.5)),
+        ("-2^-2", Ok(-0.25)),
+        ("2^3x", Err(UndefinedIdent("x".into(), Span::new(3, 4)))),
+        ("x = 2", Ok(2.0)),
+        ("2^3x", Ok(16.0)),
+        ("1/2x", Ok(1.0)),
+        ("-x^2 + 10 - 4 - 3", Ok(-1.0)),
+        ("8 / 4 / 2", Ok(1.0)),
+        ("2 * -3^2", Ok(-18.0)),
+        ("-pi", Ok(-3.1415927)),
+        ("*3", Err(UnexpectedCharacter('*', Span::new(0, 1)))),
+        ("3 ^ ^ 2", Err(UnexpectedCharacter('^', Span::new(4, 5)))),
+    ];
+
+    for (expr, result) in tests.into_iter() {
+        assert_eq!(
+            eval_math(expr, AngleMode::Rad, &mut env).map(|f| f.map(|f| to_fixed(f, 7))),
+            result.map(Some),
+            "{}",
+            expr
+        );
+    }
+}
+
+#[test]
+fn eval_nested_parentheses() {
+    use super::CalcError::*;
+    use super::{eval_math, lex::TokenKind, to_fixed, AngleMode, Environment, Span};
+
+    let mut env = Environment::new();
+    let deep = format!("{}1{}", "(".repeat(200), ")".repeat(200));
+
+    let tests = vec![
+        (deep.as_str(), Ok(1.0)),
+        ("((2 + 3) * (4 - (1 + 1)))", Ok(10.0)),
+        ("2(3)(4)", Ok(24.0)),
+        ("max((1), (2 + (3)), 4)", Ok(5.0)),
+        ("f(a, b) = (a + (b))", Err(None)),
+        ("f((1), (2))", Ok(3.0)),
+        ("(1 + 2", Err(Some(IncompleteExpression(Span::new(0, 6))))),
+        (
+            "1 + 2)",
+            Err(Some(UnexpectedCharacter(')', Span::new(5, 6)))),
+        ),
+        (
+            "(1, 2)",
+            Err(Some(UnexpectedToken(TokenKind::Comma, Span::new(2, 3)))),
+        ),
+    ];
+
+    for (expr, result) in tests.into_iter() {
+        let expected = match result {
+            Ok(v) => Ok(Some(v)),
+            Err(None) => Ok(None),
+            Err(Some(message)) => Err(message),
+        };
+        assert_eq!(
+            eval_math(expr, AngleMode::Rad, &mut env).map(|f| f.map(|f| to_fixed(f, 7))),
+            expected,
+            "{}",
+            expr
+        );
+    }
+}
+
+#[test]
+fn eval_factorial() {
+    use super::CalcError::*;
+    use super::{eval_math, to_fixed, AngleMode, Environment, Span};
+
+    let mut env = Environment::new();
+
+    let tests = vec![
+        ("5!", Ok(120.0)),
+        ("0!", Ok(1.0)),
+        ("3!!", Ok(720.0)),
+        ("2^3!", Ok(64.0)),
+        ("-3!", Ok(-6.0)),
+        ("3! 2", Ok(12.0)),
+        ("0.5!", Ok(0.8862269)),
+        ("(-0.5)!", Ok(1.7724539)),
+        ("gamma(0.5)", Ok(1.7724539)),
+        ("gamma(5)", Ok(24.0)),
+        ("n = 5", Ok(5.0)),
+        ("k = 2", Ok(2.0)),
+        ("n!/(k!(n-k)!)", Ok(10.0)),
+        ("20!", Ok(2432902008176640000.0)),
+        ("(-3)!", Err(Domain("!".into(), -3.0, Span::new(0, 5)))),
+        ("(1 - k)!", Err(Domain("!".into(), -1.0, Span::new(0, 8)))),
+        ("!3", Err(UnexpectedCharacter('!', Span::new(0, 1)))),
+    ];
+
+    for (expr, result) in tests.into_iter() {
+        assert_eq!(
+            eval_math(expr, AngleMode::Rad, &mut env).map(|f| f.map(|f| to_fixed(f, 7))),
+            result.map(Some),
+            "{}",
+            expr
+        );
+    }
+}
+
+#[test]
+fn eval_modulo_and_percent() {
+    use super::CalcError::*;
+    use super::{eval_math, lex::TokenKind, to_fixed, AngleMode, Environment, Span};
+
+    let mut env = Environment::new();
+
+    let tests = vec![
+        ("7 % 3", Ok(1.0)),
+        ("-7 % 3", Ok(2.0)),
+        ("7 % (-3)", Ok(-2.0)),
+        ("7 % -3", Ok(-2.0)),
+        ("-7 % -3", Ok(-1.0)),
+        ("10 % -3", Ok(-2.0)),
+        ("6 % ~2", Ok(0.0)),
+        ("5 % not 0", Ok(0.0)),
+        ("-7 mod 3", Ok(2.0)),
+        ("5.5 mod 2", Ok(1.5)),
+        ("7 // 2", Ok(3.0)),
+        ("-7 // 2", Ok(-4.0)),
+        ("2 * 7 // 2 % 4", Ok(3.0)),
+        ("1 + 7 mod 4", Ok(4.0)),
+        ("10%", Ok(0.1)),
+        ("200 + 10%", Ok(220.0)),
+        ("200 - 10%", Ok(180.0)),
+        ("50 * 10%", Ok(5.0)),
+        ("50 / 10%", Ok(500.0)),
+        ("200 + 10% * 2", Ok(200.2)),
+        ("x = 50", Ok(50.0)),
+        ("x % 7", Ok(1.0)),
+        ("x%", Ok(0.5)),
+        ("x mod 7", Ok(1.0)),
+        ("10 % x", Ok(10.0)),
+        ("5 mod", Err(IncompleteExpression(Span::new(2, 5)))),
+        (
+            "mod 5",
+            Err(UnexpectedToken(TokenKind::Op("mod"), Span::new(0, 3))),
+        ),
+        ("7 / / 2", Err(UnexpectedCharacter('/', Span::new(4, 5)))),
+    ];
+
+    for (expr, result) in tests.into_iter() {
+        assert_eq!(
+            eval_math(expr, AngleMode::Rad, &mut env).map(|f| f.map(|f| to_fixed(f, 7))),
+            result.map(Some),
+            "{}",
+            expr
+        );
+    }
+}
+
+#[test]
+fn eval_conditionals() {
+    use super::CalcError::*;
+    use super::{eval_math, lex::TokenKind, to_fixed, AngleMode, Arity, Environment, Span};
+
+    let mut env = Environment::new();
+
+    let tests = vec![
+        ("3 > 2", Ok(1.0)),
+        ("3 < 2", Ok(0.0)),
+        ("2 <= 2", Ok(1.0)),
+        ("2 >= 3", Ok(0.0)),
+        ("2 == 2", Ok(1.0)),
+        ("2 != 2", Ok(0.0)),
+        ("1 + 1 == 2", Ok(1.0)),
+        ("x = 4", Ok(4.0)),
+        ("x >= 3 and x < 5", Ok(1.0)),
+        ("x < 3 or x > 5", Ok(0.0)),
+        ("1 or 0 and 0", Ok(1.0)),
+        ("not x > 3", Ok(0.0)),
+        ("not 0", Ok(1.0)),
+        ("if(x > 0, x, -x)", Ok(4.0)),
+        ("if(x < 0, x, -x)", Ok(-4.0)),
+        ("2 if(1, 3, 4) + 1", Ok(7.0)),
+        ("if(x > 0, x, (-1)!)", Ok(4.0)),
+        (
+            "if(x < 0, x, (-1)!)",
+            Err(Domain("!".into(), -1.0, Span::new(13, 18))),
+        ),
+        ("0 and (-1)!", Ok(0.0)),
+        (
+            "1 < 2 < 3",
+            Err(UnexpectedToken(TokenKind::Op("<"), Span::new(6, 7))),
+        ),
+        (
+            "if(1, 2)",
+            Err(ArgumentCount {
+                func: "if".into(),
+                expected: Arity::Fixed(3),
+                found: 2,
+                span: Span::new(0, 8),
+            }),
+        ),
+        (
+            "if = 3",
+            Err(BuiltinAssignment("if".into(), Span::new(0, 2))),
+        ),
+        ("3 and", Err(IncompleteExpression(Span::new(2, 5)))),
+    ];
+
+    for (expr, result) in tests.into_iter() {
+        assert_eq!(
+            eval_math(expr, AngleMode::Rad, &mut env).map(|f| f.map(|f| to_fixed(f, 7))),
+            result.map(Some),
+            "{}",
+            expr
+        );
+    }
+
+    let definitions = vec![
+        "fact(n) = if(n <= 1, 1, n fact(n - 1))",
+        "sign(v) = if(v > 0, 1, if(v < 0, -1, 0))",
+    ];
+    for definition in definitions.into_iter() {
+        assert_eq!(eval_math(definition, AngleMode::Rad, &mut env), Ok(None));
+    }
+
+    let tests = vec![
+        ("fact(5)", 120.0),
+        ("fact(0)", 1.0),
+        ("sign(-3) + sign(0) + sign(x)", 0.0),
+    ];
+    for (expr, result) in tests.into_iter() {
+        assert_eq!(
+            eval_math(expr, AngleMode::Rad, &mut env),
+            Ok(Some(result)),
+            "{}",
+            expr
+        );
+    }
+}
+
+#[test]
+fn eval_programmer_mode() {
+    use super::CalcError::*;
+    use super::{eval_math, AngleMode, Environment, IntegerMode, Span, WordSize};
+
+    let mut env = Environment::new();
+
+    let tests = vec![
+        ("0xFF", Ok(255.0)),
+        ("0b1010", Ok(10.0)),
+        ("0o17", Ok(15.0)),
+        ("0x10 + 0b1", Ok(17.0)),
+        ("0xff & 0b1100", Ok(12.0)),
+        ("0b1010 | 0b0101", Ok(15.0)),
+        ("6 xor 3", Ok(5.0)),
+        ("~0", Ok(-1.0)),
+        ("1 << 4", Ok(16.0)),
+        ("-16 >> 2", Ok(-4.0)),
+        ("1 + 2 << 1", Ok(6.0)),
+        ("1 | 2 == 3", Ok(1.0)),
+        ("x = 3", Ok(3.0)),
+        ("0x", Ok(0.0)),
+        ("2 0b11", Err(UnexpectedCharacter('0', Span::new(2, 3)))),
+        ("0b102", Err(UnexpectedCharacter('2', Span::new(4, 5)))),
+        ("2.5 & 1", Err(Domain("&".into(), 2.5, Span::new(0, 7)))),
+        ("~0.5", Err(Domain("~".into(), 0.5, Span::new(0, 4)))),
+        ("1 << -1", Err(Domain("<<".into(), -1.0, Span::new(0, 7)))),
+        ("0x20000000000000", Ok(9007199254740992.0)),
+        ("0x20000000000001", Err(InexactInteger(Span::new(0, 16)))),
+        ("0x8000000000000000", Ok(9223372036854775808.0)),
+        (
+            "0xFFFFFFFFFFFFFFFF & 1",
+            Err(InexactInteger(Span::new(0, 18))),
+        ),
+        (
+            "0x7FFFFFFFFFFFFFFF - 1",
+            Err(InexactInteger(Span::new(0, 18))),
+        ),
+        ("0x10000000000000000", Err(InexactInteger(Span::new(0, 19)))),
+        ("1 << 63", Ok(-9223372036854775808.0)),
+        ("1 << 64", Err(Overflow(Span::new(0, 7)))),
+        ("1 << 70", Err(Overflow(Span::new(0, 7)))),
+        ("-1 >> 64", Err(Overflow(Span::new(0, 8)))),
+        ("1 << 1E30", Err(Overflow(Span::new(0, 9)))),
+        ("(1 << 53) | 1", Err(InexactInteger(Span::new(0, 13)))),
+    ];
+
+    for (expr, result) in tests.into_iter() {
+        assert_eq!(
+            eval_math(expr, AngleMode::Rad, &mut env),
+            result.map(Some),
+            "{}",
+            expr
+        );
+    }
+
+    let modes = vec![
+        (IntegerMode::new(WordSize::Bits8, false), "~0", 255.0),
+        (IntegerMode::new(WordSize::Bits8, false), "0xFF << 1", 254.0),
+        (IntegerMode::new(WordSize::Bits8, true), "0x7F | 0x80", -1.0),
+        (
+            IntegerMode::new(WordSize::Bits16, false),
+            "1 << 15",
+            32768.0,
+        ),
+        (
+            IntegerMode::new(WordSize::Bits32, true),
+            "0xFFFFFFFF & 0xFFFFFFFF",
+            -1.0,
+        ),
+        (IntegerMode::new(WordSize::Bits32, false), "~0 >> 28", 15.0),
+        (
+            IntegerMode::new(WordSize::Bits64, false),
+            "1 << 40",
+            1099511627776.0,
+        ),
+        (
+            IntegerMode::new(WordSize::Bits64, false),
+            "1 << 63",
+            9223372036854775808.0,
+        ),
+        (
+            IntegerMode::new(WordSize::Bits64, false),
+            "0xFF << 56",
+            18374686479671623680.0,
+        ),
+    ];
+
+    for (mode, expr, result) in modes.into_iter() {
+        env.set_integer_mode(mode);
+        assert_eq!(
+            eval_math(expr, AngleMode::Rad, &mut env),
+            Ok(Some(result)),
+            "{} ({})",
+            expr,
+            mode
+        );
+    }
+    assert_eq!(
+        eval_math("~0", AngleMode::Rad, &mut env),
+        Err(InexactInteger(Span::new(0, 2)))
+    );
+
+    env.set_integer_mode(IntegerMode::new(WordSize::Bits8, false));
+    for expr in &["1 << 8", "0xFF >> 8", "1 << 264"] {
+        assert_eq!(
+            eval_math(expr, AngleMode::Rad, &mut env),
+            Err(Overflow(Span::new(0, expr.len()))),
+            "{}",
+            expr
+        );
+    }
+}
+
+#[test]
+fn eval_complex_mode() {
+    use super::CalcError::*;
+    use super::{
+        eval_complex, eval_math, format_complex, AngleMode, BackendKind, ComplexFormat,
+        Environment, Span,
+    };
+
+    let mut env = Environment::new();
+    env.set_backend(BackendKind::Complex);
+
+    let tests = vec![
+        ("sqrt(-4)", Ok("2i")),
+        ("e^(i pi)", Ok("-1")),
+        ("i^2", Ok("-1")),
+        ("2i - 3", Ok("-3 + 2i")),
+        ("(1 + 2i)(3 - i)", Ok("5 + 5i")),
+        ("1 / i", Ok("-i")),
+        ("ln(-1)", Ok("3.1415927i")),
+        ("log(-100)", Ok("2 + 1.3643764i")),
+        ("asin(2)", Ok("1.5707963 - 1.3169579i")),
+        ("acos(2)", Ok("1.3169579i")),
+        ("(-8)^(1/3)", Ok("1 + 1.7320508i")),
+        ("root(-8, 3)", Ok("-2")),
+        ("abs(3 + 4i)", Ok("5")),
+        ("gamma(i)", Ok("-0.1549498 - 0.4980157i")),
+        ("z = 1 + i", Ok("1 + i")),
+        ("z^2", Ok("2i")),
+        ("i2", Ok("2i")),
+        ("i == i", Ok("1")),
+        ("if(z == 1 + i, 1, 2)", Ok("1")),
+        ("5! + 2i", Ok("120 + 2i")),
+        ("atan2(1 + i, 2)", Ok("0.5535744 + 0.4023595i")),
+        ("nCr(4 + i, 2)", Ok("5.5 + 3.5i")),
+        ("nPr(4 + i, 2)", Ok("11 + 7i")),
+        ("polygamma(0, 1 + i)", Ok("0.0946503 + 1.076674i")),
+        ("polygamma(1, 2 + 3i)", Ok("0.1355554 - 0.26701i")),
+        ("polygamma(2, -0.5 + i)", Ok("-0.0563581 - 0.026113i")),
+        ("min(i, 2)", Err(NonReal("min".into(), Span::new(0, 9)))),
+        ("max(1, 2i)", Err(NonReal("max".into(), Span::new(0, 10)))),
+        (
+            "polygamma(i, 2)",
+            Err(NonReal("polygamma".into(), Span::new(0, 15))),
+        ),
+        ("i < 2", Err(NonReal("<".into(), Span::new(0, 5)))),
+        ("i = 2", Err(BuiltinAssignment("i".into(), Span::new(0, 1)))),
+        ("ln(0)", Err(Domain("ln".into(), 0.0, Span::new(0, 5)))),
+        ("log(0, 2)", Err(Domain("log".into(), 0.0, Span::new(0, 9)))),
+        ("gamma(200 + i)", Err(Overflow(Span::new(0, 14)))),
+        ("(10 + i)^400", Err(Overflow(Span::new(0, 12)))),
+    ];
+
+    for (expr, result) in tests.into_iter() {
+        assert_eq!(
+            eval_complex(expr, AngleMode::Rad, &mut env).map(|c| c.map(|c| format_complex(
+                c,
+                ComplexFormat::Rectangular,
+                AngleMode::Rad
+            ))),
+            result.map(|r| Some(r.to_string())),
+            "{}",
+            expr
+        );
+    }
+
+    let polar = vec![
+        ("2i", AngleMode::Rad, "2∠1.5707963"),
+        ("2i", AngleMode::Deg, "2∠90°"),
+        ("-1 - i", AngleMode::Deg, "1.4142136∠-135°"),
+        ("2i", AngleMode::Grad, "2∠100grad"),
+        ("3", AngleMode::Rad, "3∠0"),
+    ];
+
+    for (expr, mode, result) in polar.into_iter() {
+        assert_eq!(
+            eval_complex(expr, mode, &mut env).map(|c| c.map(|c| format_complex(
+                c,
+                ComplexFormat::Polar,
+                mode
+            ))),
+            Ok(Some(result.to_string())),
+            "{}",
+            expr
+        );
+    }
+
+    env.set_backend(BackendKind::Float);
+
+    let tests = vec![
+        ("z", Err(ComplexValue(Span::new(0, 1)))),
+        ("i", Err(UndefinedIdent("i".into(), Span::new(0, 1)))),
+        ("i = 2", Ok(Some(2.0))),
+        ("sqrt(4) + i", Ok(Some(4.0))),
+    ];
+
+    for (expr, result) in tests.into_iter() {
+        assert_eq!(
+            eval_math(expr, AngleMode::Rad, &mut env),
+            result,
+            "{}",
+            expr
+        );
+    }
+}
+
+#[test]
+fn eval_backends() {
+    use super::CalcError::*;
+    use super::{evaluate, AngleMode, BackendKind, Environment, Span};
+
+    let tests = vec![
+        (BackendKind::Float, "0.1 + 0.2", Ok("0.30000000000000004")),
+        (BackendKind::Rational, "0.1 + 0.2", Ok("0.3")),
+        (
+            BackendKind::Rational,
+            "2^100",
+            Ok("1267650600228229401496703205376"),
+        ),
+        (
+            BackendKind::Rational,
+            "30!",
+            Ok("265252859812191058636308480000000"),
+        ),
+        (BackendKind::Rational, "1 / 3", Ok("1/3")),
+        (BackendKind::Rational, "2^-3", Ok("0.125")),
+        (BackendKind::Rational, "-7 mod 3", Ok("2")),
+        (BackendKind::Rational, "sqrt(9/4)", Ok("1.5")),
+        (
+            BackendKind::Rational,
+            "1 / 0",
+            Err(DivisionByZero(Span::new(0, 5))),
+        ),
+        (BackendKind::Rational, "x = 2 / 3", Ok("2/3")),
+        (BackendKind::Rational, "3x", Ok("2")),
+        (
+            BackendKind::Decimal(50),
+            "1 / 3",
+            Ok("0.33333333333333333333333333333333333333333333333333"),
+        ),
+        (
+            BackendKind::Decimal(50),
+            "sqrt(2)",
+            Ok("1.4142135623730950488016887242096980785696718753769"),
+        ),
+        (BackendKind::Decimal(10), "1 / 3", Ok("0.3333333333")),
+        (BackendKind::Decimal(10), "x", Ok("0.6666666667")),
+        (BackendKind::Decimal(10), "2^40", Ok("1099511628000")),
+        (
+            BackendKind::Rational,
+            "(2^5000)^5000",
+            Err(Overflow(Span::new(0, 13))),
+        ),
+        (
+            BackendKind::Rational,
+            "(2^10000)^1000",
+            Err(Overflow(Span::new(0, 14))),
+        ),
+        (
+            BackendKind::Decimal(50),
+            "(2^5000)^5000",
+            Err(Overflow(Span::new(0, 13))),
+        ),
+        (
+            BackendKind::Rational,
+            "(1 / 10^1000)^2000",
+            Err(Overflow(Span::new(0, 18))),
+        ),
+        (BackendKind::Float, "x", Ok("0.6666666666666666")),
+    ];
+
+    let mut env = Environment::new();
+    for (backend, expr, result) in tests.into_iter() {
+        env.set_backend(backend);
+        assert_eq!(
+            evaluate(expr, AngleMode::Rad, &mut env).map(|v| v.map(|v| v.to_string())),
+            result.map(|r| Some(r.to_string())),
+            "{}",
+            expr
+        );
+    }
+}
+
+#[test]
+fn eval_errors() {
+    use super::CalcError::*;
+    use super::{eval_complex, eval_math, AngleMode, BackendKind, Environment, Span};
+
+    let mut env = Environment::new();
+    let tests = vec![
+        ("1 / 0", Err(DivisionByZero(Span::new(0, 5)))),
+        ("5 mod 0", Err(DivisionByZero(Span::new(0, 7)))),
+        ("5 // 0", Err(DivisionByZero(Span::new(0, 6)))),
+        ("0^-1", Err(DivisionByZero(Span::new(0, 4)))),
+        (
+            "sqrt(-1)",
+            Err(Domain("sqrt".into(), -1.0, Span::new(0, 8))),
+        ),
+        ("ln(0)", Err(Domain("ln".into(), 0.0, Span::new(0, 5)))),
+        ("log(8, 1)", Err(Domain("log".into(), 1.0, Span::new(0, 9)))),
+        ("acos(2)", Err(Domain("acos".into(), 2.0, Span::new(0, 7)))),
+        (
+            "gamma(-2)",
+            Err(Domain("gamma".into(), -2.0, Span::new(0, 9))),
+        ),
+        (
+            "nCr(2.5, 1)",
+            Err(Domain("nCr".into(), 2.5, Span::new(0, 11))),
+        ),
+        (
+            "(-8)^(1/3)",
+            Err(Domain("^".into(), -8.0, Span::new(0, 10))),
+        ),
+        ("10^400", Err(Overflow(Span::new(0, 6)))),
+        ("200!", Err(Overflow(Span::new(0, 4)))),
+        ("10^200 * 10^200", Err(Overflow(Span::new(0, 15)))),
+        ("10^-200 * 10^-200", Err(Underflow(Span::new(0, 17)))),
+        ("2^-2000", Err(Underflow(Span::new(0, 7)))),
+        ("if(1, 2, 1 / 0)", Ok(Some(2.0))),
+        ("0 * 10^-200", Ok(Some(0.0))),
+    ];
+
+    for (expr, result) in tests.into_iter() {
+        assert_eq!(
+            eval_math(expr, AngleMode::Rad, &mut env),
+            result,
+            "{}",
+            expr
+        );
+    }
+
+    env.set_backend(BackendKind::Complex);
+    assert_eq!(
+        eval_complex("i / 0", AngleMode::Rad, &mut env),
+        Err(DivisionByZero(Span::new(0, 5)))
+    );
+    assert!(eval_complex("sqrt(-1)", AngleMode::Rad, &mut env).is_ok());
+}
+
+#[test]
+fn error_messages() {
+    use super::{eval_math, AngleMode, Environment};
+
+    let mut env = Environment::new();
+    let tests = vec![
+        ("5 + 3 -", "Incomplete expression"),
+        ("3 .20", "Unexpected character '.' at index 2"),
+        ("3 + abs - 2", "Unexpected operator -"),
+        ("3 = x", "Unexpected token '='"),
+        ("y + 1", "Undefined variable \"y\""),
+        ("pi = 3", "Cannot assign to built-in \"pi\""),
+        ("log(1, 2, 3)", "\"log\" expects 1 to 2 arguments, found 3"),
+        ("(-3)!", "\"!\" is undefined for -3"),
+        ("1 / 0", "Division by zero"),
+        ("10^400", "Result is too large"),
+        ("solve(x^2 + 1, x)", "\"solve\" did not converge"),
+        ("sum(k, k, 1, 1E9)", "Iteration limit exceeded in \"sum\""),
+        (
+            "0xFFFFFFFFFFFFFFFF",
+            "Integer is too large to represent exactly",
+        ),
+    ];
+
+    for (expr, message) in tests.into_iter() {
+        assert_eq!(
+            eval_math(expr, AngleMode::Rad, &mut env).map_err(|e| e.to_string()),
+            Err(message.to_string()),
+            "{}",
+            expr
+        );
+    }
+}
+
+#[test]
+fn simplify_expressions() {
+    use super::BackendKind::*;
+    use super::{evaluate, simplify, AngleMode, Environment};
+
+    let tests = vec![
+        (Float, "1 + 2 * 3", "7"),
+        (Float, "y = 2 ^ 10", "y = 1024"),
+        (Float, "f(x) = x * 1 + 0", "f(x) = x"),
+        (Float, "f(x) = --x^1", "f(x) = x"),
+        (Float, "f(x) = 1 * x / 1 - 0", "f(x) = x"),
+        (Float, "f(x) = ((x + 1)) * (2 + 3)", "f(x) = (x + 1) * 5"),
+        (Float, "f(x) = 2 * 3x", "f(x) = 6 * x"),
+        (Float, "f(x) = x^(2^3)", "f(x) = x^8"),
+        (Float, "f(x) = (x^2)^3", "f(x) = (x^2)^3"),
+        (Float, "f(x) = (-x)^2 - -x", "f(x) = (-x)^2 - -x"),
+        (Float, "f(x) = -(x + 1)", "f(x) = -(x + 1)"),
+        (Float, "f(x) = x - (1 - x)", "f(x) = x - (1 - x)"),
+        (Float, "f(x) = (x + 1)! / x", "f(x) = (x + 1)! / x"),
+        (Float, "f(x) = sqrt(16)x + pi * 0", "f(x) = 4 * x"),
+        (Float, "f(x) = if(1, x, 1 / 0)", "f(x) = x"),
+        (Float, "f(x) = x / 0 + ln(0)", "f(x) = x / 0 + ln(0)"),
+        (Float, "f(x) = not x and 2 > 1", "f(x) = not x and 1"),
+        (Float, "f(x) = x + 5%", "f(x) = x + 5%"),
+        (Float, "f(x) = x + (5%)", "f(x) = x + 0.05"),
+        (Float, "f(x) = x + 1 * x%", "f(x) = x + (x%)"),
+        (Float, "f(x) = 0 + x%", "f(x) = 0 + x%"),
+        (Float, "f(x) = max(x, 2 - 3, -x)", "f(x) = max(x, -1, -x)"),
+        (Float, "f(x) = x grad + 0rad", "f(x) = x grad"),
+        (Float, "f(x) = (2x)° + 1", "f(x) = (2 * x)° + 1"),
+        (Complex, "f(x) = x * (2 + 3i)", "f(x) = x * (2 + 3i)"),
+        (Complex, "f(x) = x - i * 2", "f(x) = x - 2i"),
+        (Rational, "f(x) = x + 1 / 3", "f(x) = x + 1/3"),
+        (Rational, "f(x) = x^(-2 / 3 * 3)", "f(x) = x^(-2)"),
+    ];
+
+    let mut env = Environment::new();
+    for (backend, expr, result) in tests.into_iter() {
+        env.set_backend(backend);
+        let simplified = simplify(expr, AngleMode::Rad, &env);
+        assert_eq!(
+            simplified.as_ref().map(String::as_str),
+            Ok(result),
+            "{}",
+            expr
+        );
+
+        let mut values = vec![];
+        for statement in &[expr, result] {
+            evaluate(statement, AngleMode::Rad, &mut env).unwrap();
+            let value = evaluate("f(3)", AngleMode::Rad, &mut env);
+            values.push(value.map(|v| v.map(|v| v.to_string())));
+        }
+        assert_eq!(values[0], values[1], "{}", expr);
+    }
+}
+
+#[test]
+fn derivatives() {
+    use super::AngleMode::*;
+    use super::CalcError::*;
+    use super::{deriv, diff, eval_math, parse_expression, to_fixed, Environment, Span};
+
+    let mut env = Environment::new();
+    eval_math("g(a, b) = a^2 * b", Rad, &mut env).unwrap();
+    eval_math("h(t) = t! / 2", Rad, &mut env).unwrap();
+
+    let tests = vec![
+        (Rad, "x^2", Ok("2 * x")),
+        (Rad, "3x^2 + 2x + 1", Ok("3 * (2 * x) + 2")),
+        (
+            Rad,
+            "sin(x)cos(x)",
+            Ok("cos(x) * cos(x) + sin(x) * -sin(x)"),
+        ),
+        (Deg, "sin(x)", Ok("cos(x) * 0.017453292519943295")),
+        (Rad, "x^x", Ok("x^x * (ln(x) + x / x)")),
+        (Rad, "2^x", Ok("2^x * 0.6931471805599453")),
+        (Rad, "ln(x^2 + 1)", Ok("2 * x / (x^2 + 1)")),
+        (Rad, "1 / x", Ok("-1 / x^2")),
+        (Rad, "sqrt(x)", Ok("1 / (2 * sqrt(x))")),
+        (Rad, "log(x, 2)", Ok("1 / x / 0.6931471805599453")),
+        (Rad, "g(x, 3)", Ok("2 * x * 3")),
+        (Rad, "x!", Ok("gamma(x + 1) * polygamma(0, x + 1)")),
+        (Rad, "x + 5%", Ok("1.05")),
+        (Rad, "if(x > 0, x, -x)", Ok("if(x > 0, 1, -1)")),
+        (
+            Rad,
+            "max(x, x^2, 2)",
+            Ok("if(x >= max(x^2, 2), 1, if(x^2 >= 2, 2 * x, 0))"),
+        ),
+        (Rad, "tan(2x)", Ok("2 / cos(2 * x)^2")),
+        (Rad, "x mod 3", Ok("1")),
+        (Rad, "pi", Ok("0")),
+        (
+            Rad,
+            "x & 3",
+            Err(NonDifferentiable("&".into(), Span::new(0, 5))),
+        ),
+        (
+            Rad,
+            "nCr(x, 2)",
+            Err(NonDifferentiable("nCr".into(), Span::new(0, 9))),
+        ),
+    ];
+
+    let params = vec!["x".to_string()];
+    for (mode, expr, result) in tests.into_iter() {
+        let parsed = parse_expression(expr, &params, &env).unwrap();
+        let derivative = diff(&parsed, "x", mode, &env);
+        assert_eq!(
+            derivative.map(|d| d.to_string()),
+            result.map(String::from),
+            "{}",
+            expr
+        );
+    }
+
+    let functions = vec![
+        "x^3 - 2x",
+        "sin(x)^2",
+        "cos(3x)",
+        "tan(x)",
+        "asin(x / 2)",
+        "acos(x / 2)",
+        "atan(x)",
+        "atan2(x, 2)",
+        "hypot(x, 2)",
+        "e^x",
+        "log(x)",
+        "abs(x - 1)",
+        "root(x, 3)",
+        "gamma(x)",
+        "h(x)",
+        "polygamma(1, x)",
+        "x / (1 + x^2)",
+        "(x + 1)^x",
+    ];
+    for mode in &[Rad, Deg] {
+        for expr in &functions {
+            let parsed = parse_expression(expr, &params, &env).unwrap();
+            let derivative = diff(&parsed, "x", *mode, &env).unwrap();
+
+            let (x, h) = (0.7, 1E-6);
+            let at = |x: f64| parsed.get_value_at(&super::FloatBackend, *mode, &env, "x", x);
+            let numeric = (at(x + h).unwrap() - at(x - h).unwrap()) / (2.0 * h);
+            let exact = derivative
+                .get_value_at(&super::FloatBackend, *mode, &env, "x", x)
+                .unwrap();
+            assert_eq!(to_fixed(exact, 4), to_fixed(numeric, 4), "{}", expr);
+        }
+    }
+
+    let points = vec![
+        ("sin", 0.0, Ok(1.0)),
+        ("ln", 2.0, Ok(0.5)),
+        ("gamma", 1.0, Ok(-0.5772157)),
+        ("h", 1.0, Ok(0.2113922)),
+        (
+            "g",
+            1.0,
+            Err(ArgumentCount {
+                func: "g".into(),
+                expected: super::Arity::Fixed(2),
+                found: 1,
+                span: Span::new(0, 1),
+            }),
+        ),
+        ("pi", 1.0, Err(NonFunction("pi".into(), Span::new(0, 2)))),
+        ("y", 1.0, Err(UndefinedIdent("y".into(), Span::new(0, 1)))),
+    ];
+    for (func, x0, result) in points.into_iter() {
+        assert_eq!(
+            deriv(func, x0, Rad, &env).map(|v| to_fixed(v, 7)),
+            result,
+            "{}",
+            func
+        );
+    }
+
+    let mut env = Environment::new();
+    eval_math("x = 3", Rad, &mut env).unwrap();
+    eval_math("f(t) = t * x", Rad, &mut env).unwrap();
+    for var in &["x", "y"] {
+        let parsed = parse_expression(&format!("f({})", var), &[var.to_string()], &env).unwrap();
+        let derivative = diff(&parsed, var, Rad, &env);
+        assert_eq!(derivative.map(|d| d.to_string()), Ok("3".into()), "{}", var);
+    }
+}
+
+#[test]
+fn compiled_evaluation() {
+    use super::{compile, eval_math, AngleMode, Environment, VariableValue};
+
+    let mut env = Environment::new();
+    for def in &[
+        "fact(n) = if(n <= 1, 1, n * fact(n - 1))",
+        "g(a, b) = a^2 + b",
+        "loop(n) = loop(n + 1)",
+        "scale = 3",
+        "h(t) = scale * t",
+    ] {
+        eval_math(def, AngleMode::Rad, &mut env).unwrap();
+    }
+
+    let exprs = vec![
+        "3x^2 + 2x + 1",
+        "sin(x)cos(y) + atan2(y, x)",
+        "g(x, y) * fact(5)",
+        "fact(x)",
+        "x + 10%",
+        "x - y%",
+        "x > 1 and y / 0",
+        "x < 1 or 1 / 0",
+        "if(x > y, x, y)",
+        "max(x, y, 2) + min(3, x)",
+        "x & 3 | y << 2",
+        "~x",
+        "x! + x mod 2",
+        "sqrt(x - 2)",
+        "ln(x - y)",
+        "x / (y - 2)",
+        "10^(x * 200)",
+        "x^-2000 * 2",
+        "loop(x)",
+        "h(x) + y",
+    ];
+    let vars = vec!["x".to_string(), "y".to_string()];
+    let points = [(1.0, 2.0), (2.0, 1.0), (0.5, -3.0), (4.0, 2.0)];
+
+    for mode in &[AngleMode::Rad, AngleMode::Deg] {
+        for expr in &exprs {
+            let compiled = compile(expr, &vars, *mode, &env).unwrap();
+            assert_eq!(compiled.vars(), 2);
+            for &(x, y) in points.iter() {
+                env.set("x".to_string(), VariableValue::Constant(x));
+                env.set("y".to_string(), VariableValue::Constant(y));
+                let expected = eval_math(expr, *mode, &mut env).map(Option::unwrap);
+                assert_eq!(compiled.eval(&[x, y]), expected, "{} at {:?}", expr, (x, y));
+            }
+        }
+    }
+}
+
+#[test]
+fn column_evaluation() {
+    use super::CalcError::*;
+    use super::{compile, eval_math, AngleMode, Arity, Environment, Span};
+
+    let mut env = Environment::new();
+    for def in &[
+        "fact(n) = if(n <= 1, 1, n * fact(n - 1))",
+        "fib(n) = if(n < 2, n, fib(n - 1) + fib(n - 2))",
+        "loop(n) = if(n > 3, loop(n + 1), n)",
+        "scale = 3",
+        "h(t) = scale * t",
+    ] {
+        eval_math(def, AngleMode::Rad, &mut env).unwrap();
+    }
+
+    let exprs = vec![
+        "3x^2 + 2x + 1",
+        "sin(x)cos(y) + atan2(y, x)",
+        "fact(abs(x)) + fib(floor(abs(y)))",
+        "x - y%",
+        "x > 1 and y / (x - 2)",
+        "x < 1 or ln(x - 1)",
+        "if(x > y, sqrt(x - y), 1 / (y - x))",
+        "x & 3 | floor(y) << 2",
+        "10^(x * 200) + loop(y)",
+        "x^-2000 * 2",
+        "h(x) - y",
+    ];
+    let vars = vec!["x".to_string(), "y".to_string()];
+    let xs: Vec<f64> = (0..12_000)
+        .map(|i| f64::from(i % 120) / 10.0 - 6.0)
+        .collect();
+    let ys: Vec<f64> = (0..12_000)
+        .map(|i| f64::from(i / 120) / 10.0 - 5.0)
+        .collect();
+
+    for mode in &[AngleMode::Rad, AngleMode::Deg] {
+        for expr in &exprs {
+            let compiled = compile(expr, &vars, *mode, &env).unwrap();
+            let expected: Vec<_> = xs
+                .iter()
+                .zip(&ys)
+                .map(|(&x, &y)| compiled.eval(&[x, y]))
+                .collect();
+            assert_eq!(compiled.eval_columns(&[&xs, &ys]), expected, "{}", expr);
+            assert_eq!(
+                compiled.eval_columns_parallel(&[&xs, &ys], 4),
+                expected,
+                "{}",
+                expr
+            );
+            assert_eq!(
+                compiled.eval_columns(&[&xs[..3], &ys[..3]]),
+                &expected[..3],
+                "{}",
+                expr
+            );
+        }
+    }
+
+    let constant = compile("2 + 3", &[], AngleMode::Rad, &env).unwrap();
+    assert_eq!(constant.eval_columns(&[]), vec![Ok(5.0)]);
+
+    let compiled = compile("x + y", &vars, AngleMode::Rad, &env).unwrap();
+    let count = |found| ArgumentCount {
+        func: "eval".into(),
+        expected: Arity::Fixed(2),
+        found,
+        span: Span::new(0, 5),
+    };
+    assert_eq!(compiled.eval(&[1.0]), Err(count(1)));
+    assert_eq!(compiled.eval(&[1.0, 2.0, 3.0]), Err(count(3)));
+    assert_eq!(compiled.eval_columns(&[&xs[..2]]), vec![Err(count(1)); 2]);
+    assert_eq!(
+        compiled.eval_columns_parallel(&[&xs, &ys[..1]], 4)[..2],
+        [Ok(-11.0), Err(count(1))]
+    );
+}
+
+#[test]
+fn solve_equations() {
+    use super::CalcError::*;
+    use super::{
+        compile, diff, eval_math, lex::TokenKind, parse_expression, simplify, solve, solve_all,
+        solve_variable, to_fixed, AngleMode, Arity, Environment, FloatBackend, Span, VariableValue,
+    };
+
+    let mut env = Environment::new();
+    eval_math(
+        "r(a) = solve(x^2 - a, x, 0, a + 1)",
+        AngleMode::Rad,
+        &mut env,
+    )
+    .unwrap();
+
+    let tests = vec![
+        ("solve(x^3 - 2x - 5, x)", Ok(2.0945515)),
+        ("solve(x^2 - 4, x, -5)", Ok(-2.0)),
+        ("solve(cos(x) - x, x, 0, 1)", Ok(0.7390851)),
+        ("solve(ln(x) - 1, x)", Ok(2.7182818)),
+        ("solve(x^2 - 2x + 1, x, 3)", Ok(1.0)),
+        ("solve(sin(x), x, 3)", Ok(3.1415927)),
+        ("r(2)", Ok(1.4142136)),
+        ("r(9) + solve(t - r(4), t)", Ok(5.0)),
+        ("x = 5", Ok(5.0)),
+        ("solve(x - 3, x) + x", Ok(8.0)),
+        ("solve(solve(y - x, y) - 2, x)", Ok(2.0)),
+        ("solve(x2 - 6, x)", Ok(3.0)),
+        (
+            "solve(x^2 + 1, x)",
+            Err(NoConvergence("solve".into(), Span::new(0, 17))),
+        ),
+        (
+            "solve(1 / x, x, -1, 1)",
+            Err(NoConvergence("solve".into(), Span::new(0, 22))),
+        ),
+        (
+            "solve(x - 1)",
+            Err(ArgumentCount {
+                func: "solve".into(),
+                expected: Arity::Range(2, 4),
+                found: 1,
+                span: Span::new(0, 12),
+            }),
+        ),
+        (
+            "solve(x, x, 1, 2, 3)",
+            Err(ArgumentCount {
+                func: "solve".into(),
+                expected: Arity::Range(2, 4),
+                found: 5,
+                span: Span::new(0, 20),
+            }),
+        ),
+        (
+            "solve(x, 1)",
+            Err(UnexpectedToken(TokenKind::Number(1.0), Span::new(9, 10))),
+        ),
+        (
+            "solve(sin, sin)",
+            Err(BuiltinAssignment("sin".into(), Span::new(11, 14))),
+        ),
+    ];
+    for (expr, result) in tests.into_iter() {
+        assert_eq!(
+            eval_math(expr, AngleMode::Rad, &mut env).map(|f| f.map(|f| to_fixed(f, 7))),
+            result.map(Some),
+            "{}",
+            expr
+        );
+    }
+    assert_eq!(
+        eval_math("solve(sin(x) - 0.5, x)", AngleMode::Deg, &mut env)
+            .map(|f| f.map(|f| to_fixed(f, 7))),
+        Ok(Some(30.0))
+    );
+
+    let env = Environment::new();
+    assert_eq!(
+        simplify("solve(x^2 - 2 * 2, x, 1 + 1)", AngleMode::Rad, &env),
+        Ok("solve(x^2 - 4, x, 2)".to_string())
+    );
+    assert_eq!(
+        solve("x^2 = 2x + 3", "x", AngleMode::Rad, &env).map(|f| to_fixed(f, 7)),
+        Ok(-1.0)
+    );
+    assert_eq!(
+        solve("x^2 = -1", "x", AngleMode::Rad, &env),
+        Err(NoConvergence("solve".into(), Span::new(0, 8)))
+    );
+    let variables = vec![
+        ("x^2 = 2x + 3", Ok("x")),
+        ("t^3 - 2t - 5", Ok("t")),
+        ("y + sin(y) + pi", Ok("y")),
+        ("sum(k * z, k, 1, 3) - 6", Ok("z")),
+        ("2 + 3", Err(SolveVariables(vec![], Span::new(0, 5)))),
+        (
+            "x * y = 1",
+            Err(SolveVariables(
+                vec!["x".into(), "y".into()],
+                Span::new(0, 9),
+            )),
+        ),
+        ("x + ", Err(IncompleteExpression(Span::new(2, 3)))),
+    ];
+    for (expr, result) in variables.into_iter() {
+        assert_eq!(
+            solve_variable(expr, &env),
+            result.map(String::from),
+            "{}",
+            expr
+        );
+    }
+
+    let roots = solve_all("sin(x)", "x", 10.0, -10.0, AngleMode::Rad, &env).unwrap();
+    let expected: Vec<f64> = (-3..=3)
+        .map(|n| to_fixed(f64::from(n) * ::std::f64::consts::PI, 7))
+        .collect();
+    assert_eq!(
+        roots
+            .into_iter()
+            .map(|root| to_fixed(root, 7))
+            .collect::<Vec<_>>(),
+        expected
+    );
+
+    let vars = vec!["a".to_string()];
+    let expr = parse_expression("solve(x^2 - a, x, 0, 10)", &vars, &env).unwrap();
+    let derivative = diff(&expr, "a", AngleMode::Rad, &env).unwrap();
+    assert_eq!(
+        derivative
+            .get_value_at(&FloatBackend, AngleMode::Rad, &env, "a", 4.0)
+            .map(|f| to_fixed(f, 7))
+            .unwrap(),
+        0.25
+    );
+
+    let mut env = Environment::new();
+    let source = "solve(x^2 - a, x, 0, a + 1) * solve(x - a, x)";
+    let compiled = compile(source, &vars, AngleMode::Rad, &env).unwrap();
+    let points = [-1.0, 0.5, 2.0, 7.0];
+    for &a in points.iter() {
+        env.set("a".to_string(), VariableValue::Constant(a));
+        let expected = eval_math(source, AngleMode::Rad, &mut env).map(Option::unwrap);
+        assert_eq!(compiled.eval(&[a]), expected, "a = {}", a);
+    }
+    let expected: Vec<_> = points.iter().map(|&a| compiled.eval(&[a])).collect();
+    assert_eq!(compiled.eval_columns(&[&points]), expected);
+}
+
+#[test]
+fn bound_operators() {
+    use super::CalcError::*;
+    use super::{
+        compile, diff, eval_math, integrate, parse_expression, to_fixed, AngleMode, Arity,
+        Environment, FloatBackend, Span, VariableValue,
+    };
+
+    let mut env = Environment::new();
+    eval_math("f(n) = sum(k^2, k, 1, n)", AngleMode::Rad, &mut env).unwrap();
+
+    let tests = vec![
+        ("integrate(x^2, x, 0, 3)", Ok(9.0)),
+        ("integrate(sin(x), x, 0, pi)", Ok(2.0)),
+        ("integrate(e^-x^2, x, -5, 5)", Ok(1.7724539)),
+        ("integrate(x, x, 2, 0)", Ok(-2.0)),
+        ("integrate(1 / sqrt(x), x, 0, 1)", Ok(2.0)),
+        ("integrate(sum(x^k, k, 0, 2), x, 0, 1)", Ok(1.8333333)),
+        ("sum(k, k, 1, 100)", Ok(5050.0)),
+        ("sum(1 / k^2, k, 1, 10000)", Ok(1.6448341)),
+        ("prod(k, k, 1, 10)", Ok(3628800.0)),
+        ("sum(k, k, 5, 1) + prod(k, k, 5, 1)", Ok(1.0)),
+        ("f(3)", Ok(14.0)),
+        ("sum(prod(j, j, 1, k), k, 1, 4)", Ok(33.0)),
+        (
+            "sum(k, k, 1, 1E7)",
+            Err(IterationLimit("sum".into(), Span::new(0, 17))),
+        ),
+        (
+            "sum(k, k, 1.5, 3)",
+            Err(Domain("sum".into(), 1.5, Span::new(0, 17))),
+        ),
+        (
+            "sum(1, k, 1E16, 1E16 + 2)",
+            Err(Domain("sum".into(), 1E16, Span::new(0, 25))),
+        ),
+        ("sum(k, k, 2^53 - 2, 2^53)", Ok(27021597764222972.0)),
+        ("prod(10^k, k, 1, 400)", Err(Overflow(Span::new(5, 9)))),
+        ("sum(2^k, k, 1, 2000)", Err(Overflow(Span::new(4, 7)))),
+        (
+            "integrate(x, x, -1E308, 1E308)",
+            Err(Domain("integrate".into(), 1E308, Span::new(0, 30))),
+        ),
+        ("sum(1 / k, k, 0, 3)", Err(DivisionByZero(Span::new(4, 9)))),
+        (
+            "integrate(x, x, 0)",
+            Err(ArgumentCount {
+                func: "integrate".into(),
+                expected: Arity::Fixed(4),
+                found: 3,
+                span: Span::new(0, 18),
+            }),
+        ),
+    ];
+    for (expr, result) in tests.into_iter() {
+        assert_eq!(
+            eval_math(expr, AngleMode::Rad, &mut env).map(|f| f.map(|f| to_fixed(f, 7))),
+            result.map(Some),
+            "{}",
+            expr
+        );
+    }
+    assert_eq!(
+        eval_math("integrate(cos(x), x, 0, 90)", AngleMode::Deg, &mut env)
+            .map(|f| f.map(|f| to_fixed(f, 7))),
+        Ok(Some(57.2957795))
+    );
+
+    let (value, error) = integrate("x^3", "x", 0.0, 2.0, AngleMode::Rad, &env).unwrap();
+    assert_eq!(to_fixed(value, 10), 4.0);
+    assert!(error <= 1E-10);
+    assert_eq!(
+        integrate("x", "x", 0.0, f64::INFINITY, AngleMode::Rad, &env),
+        Err(Domain("integrate".into(), f64::INFINITY, Span::new(0, 1)))
+    );
+
+    let vars = vec!["a".to_string()];
+    let derivatives = vec![
+        ("integrate(t * a, t, 0, a)", Ok(6.0)),
+        ("sum(k * a^2, k, 1, 3)", Ok(24.0)),
+        ("prod(k + a, k, 1, 2)", Ok(7.0)),
+        (
+            "sum(k, k, 1, a)",
+            Err(NonDifferentiable("sum".into(), Span::new(0, 15))),
+        ),
+    ];
+    for (source, expected) in derivatives.into_iter() {
+        let expr = parse_expression(source, &vars, &env).unwrap();
+        let derivative = diff(&expr, "a", AngleMode::Rad, &env).and_then(|derivative| {
+            Ok(derivative.get_value_at(&FloatBackend, AngleMode::Rad, &env, "a", 2.0)?)
+        });
+        assert_eq!(derivative.map(|f| to_fixed(f, 7)), expected, "{}", source);
+    }
+
+    let source = "integrate(sin(a * x), x, 0, 1) + sum(a^k, k, 0, 3) * prod(k / a, k, 1, 2)";
+    let compiled = compile(source, &vars, AngleMode::Rad, &env).unwrap();
+    let points = [-1.0, 0.0, 0.5, 2.0];
+    for &a in points.iter() {
+        env.set("a".to_string(), VariableValue::Constant(a));
+        let expected = eval_math(source, AngleMode::Rad, &mut env).map(Option::unwrap);
+        assert_eq!(compiled.eval(&[a]), expected, "a = {}", a);
+    }
+    let expected: Vec<_> = points.iter().map(|&a| compiled.eval(&[a])).collect();
+    assert_eq!(compiled.eval_columns(&[&points]), expected);
+}
+
+#[test]
+fn angle_units() {
+    use super::{
+        compile, diff, eval_math, parse_expression, to_fixed, AngleMode, CalcError, Environment,
+        FloatBackend, Span, VariableValue,
+    };
+
+    let mut env = Environment::new();
+    let tests = vec![
+        ("sin(100)", AngleMode::Grad, 1.0),
+        ("asin(1) + atan2(1, 1)", AngleMode::Grad, 150.0),
+        ("sin(30°)", AngleMode::Rad, 0.5),
+        ("cos(1.2rad)", AngleMode::Deg, 0.3623578),
+        ("tan(50grad)", AngleMode::Rad, 1.0),
+        ("cos(200grad) + sin(90°)", AngleMode::Grad, 0.0),
+        ("30°", AngleMode::Rad, 0.5235988),
+        ("-90° + pi rad", AngleMode::Deg, 90.0),
+        ("2^9°", AngleMode::Grad, 1024.0),
+        ("(45 + 45)° / 3", AngleMode::Deg, 30.0),
+        ("sum(k°, k, 1, 3)", AngleMode::Grad, 6.6666667),
+    ];
+    for (expr, mode, result) in tests.into_iter() {
+        assert_eq!(
+            eval_math(expr, mode, &mut env).map(|f| f.map(|f| to_fixed(f, 7))),
+            Ok(Some(result)),
+            "{} in {}",
+            expr,
+            mode
+        );
+    }
+
+    assert_eq!(
+        eval_math("°", AngleMode::Rad, &mut env),
+        Err(CalcError::UnexpectedCharacter('°', Span::new(0, 1)))
+    );
+    eval_math("rad = 3", AngleMode::Rad, &mut env).unwrap();
+    assert_eq!(eval_math("2rad", AngleMode::Deg, &mut env), Ok(Some(6.0)));
+
+    let env = Environment::new();
+    let vars = ["x".to_string(), "y".to_string()];
+    let expr = parse_expression("(x + 1)° * y grad", &vars, &env).unwrap();
+    assert_eq!(expr.to_string(), "(x + 1)° * y grad");
+
+    let expr = parse_expression("(x + 1)° * x grad", &vars, &env).unwrap();
+    let derivative = diff(&expr, "x", AngleMode::Deg, &env).unwrap();
+    assert_eq!(
+        derivative
+            .get_value_at(&FloatBackend, AngleMode::Deg, &env, "x", 1.0)
+            .map(|f| to_fixed(f, 7))
+            .unwrap(),
+        2.7
+    );
+
+    let mut env = Environment::new();
+    let source = "sin(x°) + cos(x grad) * x rad";
+    let compiled = compile(source, &vars[..1], AngleMode::Grad, &env).unwrap();
+    let points = [-30.0, 0.0, 45.0, 100.0];
+    for &x in points.iter() {
+        env.set("x".to_string(), VariableValue::Constant(x));
+        let expected = eval_math(source, AngleMode::Grad, &mut env).map(Option::unwrap);
+        assert_eq!(compiled.eval(&[x]), expected, "x = {}", x);
+    }
+    let expected: Vec<_> = points.iter().map(|&x| compiled.eval(&[x])).collect();
+    assert_eq!(compiled.eval_columns(&[&points]), expected);
+}
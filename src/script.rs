@@ -0,0 +1,321 @@
+use parser::lex::{self, TokenKind};
+use parser::{self, AngleMode, CalcError, Environment};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const COMMENT: char = '#';
+const DIRECTIVE: char = ':';
+const INCLUDE: &str = "include";
+
+#[derive(Debug, PartialEq)]
+pub struct Output {
+    pub path: PathBuf,
+    pub line: usize,
+    pub value: f64,
+}
+
+#[derive(Debug)]
+pub enum ScriptErrorKind {
+    Calc(CalcError),
+    Io(io::Error),
+    IncludeCycle(Vec<PathBuf>),
+    UnknownDirective(String),
+}
+
+#[derive(Debug)]
+pub struct ScriptError {
+    pub path: PathBuf,
+    pub line: usize,
+    pub kind: ScriptErrorKind,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = self.path.display();
+        match &self.kind {
+            ScriptErrorKind::Calc(e) => {
+                let span = e.span();
+                write!(f, "{}:{}:{}", path, self.line, span.start + 1)?;
+                if span.end > span.start + 1 {
+                    write!(f, "-{}", span.end)?;
+                }
+                write!(f, ": {}", e)
+            }
+            ScriptErrorKind::Io(e) => write!(f, "{}:{}: {}", path, self.line, e),
+            ScriptErrorKind::IncludeCycle(chain) => {
+                let chain: Vec<_> = chain
+                    .iter()
+                    .map(|include| format!("\"{}\"", include.display()))
+                    .collect();
+                write!(
+                    f,
+                    "{}:{}: Include cycle {}",
+                    path,
+                    self.line,
+                    chain.join(" -> ")
+                )
+            }
+            ScriptErrorKind::UnknownDirective(directive) => write!(
+                f,
+                "{}:{}: Unknown directive \"{}{}\"",
+                path, self.line, DIRECTIVE, directive
+            ),
+        }
+    }
+}
+
+impl ::std::error::Error for ScriptError {}
+
+pub type Entry = Result<Output, ScriptError>;
+
+pub struct Runner {
+    pub env: Environment,
+    pub mode: AngleMode,
+    pub keep_going: bool,
+    stack: Vec<(PathBuf, PathBuf)>,
+    failed: bool,
+}
+
+impl Runner {
+    pub fn new(mode: AngleMode, keep_going: bool) -> Self {
+        Self {
+            env: Environment::new(),
+            mode,
+            keep_going,
+            stack: vec![],
+            failed: false,
+        }
+    }
+
+    pub fn run_file(&mut self, path: &Path) -> Vec<Entry> {
+        let mut entries = vec![];
+        self.failed = false;
+        self.include(path, path, 0, &mut entries);
+        entries
+    }
+
+    pub fn run_str(&mut self, source: &str, path: &Path) -> Vec<Entry> {
+        let mut entries = vec![];
+        self.failed = false;
+        self.run_source(source, path, &mut entries);
+        entries
+    }
+
+    fn include(&mut self, parent: &Path, path: &Path, line: usize, entries: &mut Vec<Entry>) {
+        let error = |kind| ScriptError {
+            path: parent.to_path_buf(),
+            line,
+            kind,
+        };
+        let canonical = match fs::canonicalize(path) {
+            Ok(canonical) => canonical,
+            Err(e) => return self.fail(error(ScriptErrorKind::Io(e)), entries),
+        };
+        if let Some(start) = self.stack.iter().position(|(file, _)| *file == canonical) {
+            let chain = self.stack[start..]
+                .iter()
+                .map(|(_, include)| include.clone())
+                .chain(Some(path.to_path_buf()))
+                .collect();
+            return self.fail(error(ScriptErrorKind::IncludeCycle(chain)), entries);
+        }
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => return self.fail(error(ScriptErrorKind::Io(e)), entries),
+        };
+
+        self.stack.push((canonical, path.to_path_buf()));
+        self.run_source(&source, path, entries);
+        self.stack.pop();
+    }
+
+    fn fail(&mut self, error: ScriptError, entries: &mut Vec<Entry>) {
+        self.failed = true;
+        entries.push(Err(error));
+    }
+
+    fn run_source(&mut self, source: &str, path: &Path, entries: &mut Vec<Entry>) {
+        for (index, line) in source.lines().enumerate() {
+            if self.failed && !self.keep_going {
+                return;
+            }
+
+            let line_number = index + 1;
+            let line = line.split(COMMENT).next().unwrap_or_default();
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+
+            if let Some(directive) = trimmed.strip_prefix(DIRECTIVE) {
+                self.directive(directive.trim(), path, line_number, entries);
+                continue;
+            }
+
+            let is_statement = lex::lex(line)
+                .map(|tokens| tokens.iter().any(|token| token.kind == TokenKind::Assign))
+                .unwrap_or(false);
+            match parser::eval_math(line, self.mode, &mut self.env) {
+                Ok(Some(value)) if !is_statement => entries.push(Ok(Output {
+                    path: path.to_path_buf(),
+                    line: line_number,
+                    value,
+                })),
+                Ok(_) => (),
+                Err(e) => {
+                    let error = ScriptError {
+                        path: path.to_path_buf(),
+                        line: line_number,
+                        kind: ScriptErrorKind::Calc(e),
+                    };
+                    self.fail(error, entries);
+                }
+            }
+        }
+    }
+
+    fn directive(&mut self, directive: &str, path: &Path, line: usize, entries: &mut Vec<Entry>) {
+        match directive {
+            "deg" => self.mode = AngleMode::Deg,
+            "rad" => self.mode = AngleMode::Rad,
+            "grad" => self.mode = AngleMode::Grad,
+            _ if directive.starts_with(INCLUDE) => {
+                let file = directive[INCLUDE.len()..].trim().trim_matches('"');
+                let include = path
+                    .parent()
+                    .map_or_else(|| PathBuf::from(file), |dir| dir.join(file));
+                self.include(path, &include, line, entries);
+            }
+            _ => {
+                let error = ScriptError {
+                    path: path.to_path_buf(),
+                    line,
+                    kind: ScriptErrorKind::UnknownDirective(directive.to_string()),
+                };
+                self.fail(error, entries);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::Span;
+
+    fn values(entries: &[Entry]) -> Vec<Result<(usize, f64), usize>> {
+        entries
+            .iter()
+            .map(|entry| match entry {
+                Ok(output) => Ok((output.line, parser::to_fixed(output.value, 7))),
+                Err(e) => Err(e.line),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn script_statements() {
+        let source = "\
+# loan calculator
+rate = 0.05 / 12
+months = 12 * 30
+payment(p) = p * rate / (1 - (1 + rate)^-months)
+
+payment(200000)  # monthly payment
+:deg
+sin(30)
+y + 1
+2 * 3
+";
+        let path = Path::new("loan.calc");
+
+        let mut runner = Runner::new(AngleMode::Rad, false);
+        let entries = runner.run_str(source, path);
+        assert_eq!(
+            values(&entries),
+            vec![Ok((6, 1073.643246)), Ok((8, 0.5)), Err(9)]
+        );
+        match &entries[2] {
+            Err(ScriptError {
+                kind: ScriptErrorKind::Calc(e),
+                ..
+            }) => assert_eq!(*e, CalcError::UndefinedIdent("y".into(), Span::new(0, 1))),
+            entry => panic!("unexpected entry {:?}", entry),
+        }
+        assert_eq!(
+            entries[2].as_ref().unwrap_err().to_string(),
+            "loan.calc:9:1: Undefined variable \"y\""
+        );
+
+        let mut runner = Runner::new(AngleMode::Rad, true);
+        let entries = runner.run_str(source, path);
+        assert_eq!(
+            values(&entries),
+            vec![Ok((6, 1073.643246)), Ok((8, 0.5)), Err(9), Ok((10, 6.0))]
+        );
+
+        let entries = runner.run_str("2 * 3\n1 + foo\n", path);
+        assert_eq!(
+            entries[1].as_ref().unwrap_err().to_string(),
+            "loan.calc:2:5-7: Undefined variable \"foo\""
+        );
+    }
+
+    #[test]
+    fn script_includes() {
+        let dir = ::std::env::temp_dir().join(format!("calc-script-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("constants.calc"), "g = 9.81\n:bogus\n").unwrap();
+        fs::write(dir.join("main.calc"), ":include constants.calc\ng * 2\n").unwrap();
+        fs::write(dir.join("cycle.calc"), ":include \"cycle.calc\"\n").unwrap();
+        fs::write(dir.join("a.calc"), "1\n:include b.calc\n").unwrap();
+        fs::write(dir.join("b.calc"), ":include a.calc\n").unwrap();
+
+        let mut runner = Runner::new(AngleMode::Rad, true);
+        let entries = runner.run_file(&dir.join("main.calc"));
+        assert_eq!(values(&entries), vec![Err(2), Ok((2, 19.62))]);
+        assert_eq!(
+            entries[0].as_ref().unwrap_err().path,
+            dir.join("constants.calc")
+        );
+
+        let mut runner = Runner::new(AngleMode::Rad, false);
+        let entries = runner.run_file(&dir.join("main.calc"));
+        assert_eq!(values(&entries), vec![Err(2)]);
+        let entries = runner.run_file(&dir.join("a.calc"));
+        assert_eq!(values(&entries), vec![Ok((1, 1.0)), Err(1)]);
+
+        let mut runner = Runner::new(AngleMode::Rad, true);
+        let entries = runner.run_file(&dir.join("cycle.calc"));
+        match &entries[..] {
+            [Err(ScriptError {
+                kind: ScriptErrorKind::IncludeCycle(_),
+                line: 1,
+                ..
+            })] => (),
+            entries => panic!("unexpected entries {:?}", entries),
+        }
+        let cycle = dir.join("cycle.calc");
+        assert_eq!(
+            entries[0].as_ref().unwrap_err().to_string(),
+            format!("{0}:1: Include cycle \"{0}\" -> \"{0}\"", cycle.display())
+        );
+
+        let mut runner = Runner::new(AngleMode::Rad, true);
+        let entries = runner.run_file(&dir.join("a.calc"));
+        assert_eq!(values(&entries), vec![Ok((1, 1.0)), Err(1)]);
+        let (a, b) = (dir.join("a.calc"), dir.join("b.calc"));
+        assert_eq!(
+            entries[1].as_ref().unwrap_err().to_string(),
+            format!(
+                "{1}:1: Include cycle \"{0}\" -> \"{1}\" -> \"{0}\"",
+                a.display(),
+                b.display()
+            )
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
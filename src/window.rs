@@ -1,5 +1,5 @@
 use super::gtk;
-use super::parser;
+use calculator::parser;
 use std::cell::RefCell;
 use std::char::from_u32;
 use std::rc::Rc;
@@ -37,8 +37,9 @@ fn format_ans(ans: f64) -> String {
 
 fn ok_key(c: char) -> bool {
     match c {
-        '(' | ')' | '.' | '-' | '+' | '*' | '/' | '^' | 'E' => true,
-        _ => c.is_digit(10),
+        '(' | ')' | '.' | '-' | '+' | '*' | '/' | '^' | '=' | '_' | ',' | '!' | '%' | '<' | '>'
+        | '&' | '|' | '~' | '°' => true,
+        _ => c.is_digit(10) || c.is_ascii_alphabetic(),
     }
 }
 
@@ -46,8 +47,12 @@ fn ok_key(c: char) -> bool {
 enum ButtonEvent {
     Inv,
     DegMode,
+    WordSize,
+    NumberMode,
     Ans,
     Evaluate,
+    Simplify,
+    Solve,
     Clear,
     Del,
 }
@@ -115,7 +120,9 @@ impl CalcButton {
 
 pub struct CalculatorState {
     angle_mode: parser::AngleMode,
-    prev_ans: Option<f64>,
+    env: parser::Environment,
+    complex_format: parser::ComplexFormat,
+    prev_ans: Option<String>,
     buttons: Vec<CalcButton>,
     textarea: Entry,
     mode_index: Option<usize>,
@@ -145,6 +152,8 @@ impl CalculatorState {
 
         Self {
             angle_mode: parser::AngleMode::Rad,
+            env: parser::Environment::new(),
+            complex_format: parser::ComplexFormat::Rectangular,
             prev_ans: None,
             buttons,
             textarea,
@@ -156,7 +165,7 @@ impl CalculatorState {
     }
 
     fn last_ans(&self) -> String {
-        self.prev_ans.map(format_ans).unwrap_or_default()
+        self.prev_ans.clone().unwrap_or_default()
     }
 
     fn clear(&mut self) {
@@ -192,22 +201,89 @@ impl CalculatorState {
     }
 
     fn evaluate(&mut self) {
-        match parser::eval_math(
-            &self.textarea.get_text().unwrap_or_default(),
-            self.angle_mode,
-        ) {
-            Ok(solution) => {
-                let fixed = parser::to_fixed(solution, 7);
-                self.prev_ans = fixed.into();
-                self.textarea.set_text(&format_ans(fixed));
+        let text = self.textarea.get_text().unwrap_or_default();
+        let answer = parser::evaluate(&text, self.angle_mode, &mut self.env).map(|solution| {
+            solution.map(|solution| match solution {
+                parser::Value::Real(solution) => {
+                    let fixed = format_ans(parser::to_fixed(solution, 7));
+                    (fixed.clone(), fixed)
+                }
+                parser::Value::Complex(solution) => {
+                    let rectangular = parser::ComplexFormat::Rectangular;
+                    (
+                        format!(
+                            "({})",
+                            parser::format_complex(solution, rectangular, self.angle_mode)
+                        ),
+                        parser::format_complex(solution, self.complex_format, self.angle_mode),
+                    )
+                }
+                exact => {
+                    let display = exact.to_string();
+                    (format!("({})", display), display)
+                }
+            })
+        });
+
+        match answer {
+            Ok(Some((ans, display))) => {
+                self.prev_ans = ans.into();
+                self.textarea.set_text(&display);
+                self.clear_next = true;
+            }
+            Ok(None) => {
+                self.clear_next = true;
+            }
+            Err(ref err) => {
+                self.err_label
+                    .set_text(&truncate_str_ellipses(&err.to_string(), 50));
+                self.highlight(err.span());
+            }
+        }
+    }
+
+    fn simplify(&mut self) {
+        let text = self.textarea.get_text().unwrap_or_default();
+        match parser::simplify(&text, self.angle_mode, &self.env) {
+            Ok(simplified) => self.textarea.set_text(&simplified),
+            Err(ref err) => {
+                self.err_label
+                    .set_text(&truncate_str_ellipses(&err.to_string(), 50));
+                self.highlight(err.span());
+            }
+        }
+    }
+
+    fn solve(&mut self) {
+        let text = self.textarea.get_text().unwrap_or_default();
+        let root = parser::solve_variable(&text, &self.env)
+            .and_then(|var| parser::solve(&text, &var, self.angle_mode, &self.env));
+        match root {
+            Ok(root) => {
+                let ans = format_ans(parser::to_fixed(root, 7));
+                self.prev_ans = ans.clone().into();
+                self.textarea.set_text(&ans);
                 self.clear_next = true;
             }
-            Err(ref msg) => {
-                self.err_label.set_text(&truncate_str_ellipses(msg, 50));
+            Err(ref err) => {
+                self.err_label
+                    .set_text(&truncate_str_ellipses(&err.to_string(), 50));
+                self.highlight(err.span());
             }
         }
     }
 
+    fn highlight(&self, span: parser::Span) {
+        let len = self.textarea.get_text_length() as usize;
+        let end = if span.start == span.end {
+            span.end + 1
+        } else {
+            span.end
+        };
+        self.textarea
+            .select_region(span.start.min(len) as i32, end.min(len) as i32);
+    }
+
     fn backspace(&self, size: u16) {
         self.textarea.delete_text(
             self.textarea
@@ -241,13 +317,35 @@ impl CalculatorState {
             Special(ButtonEvent::Clear) => self.clear(),
             Special(ButtonEvent::DegMode) => if let Some(index) = self.mode_index {
                 if let Some(button) = self.buttons.get(index) {
-                    button.button.set_label(&self.angle_mode.to_string());
-                    self.angle_mode = !self.angle_mode;
+                    self.angle_mode = self.angle_mode.cycle();
+                    button.button.set_label(&self.angle_mode.cycle().to_string());
                     self.mode_label
                         .set_label(&format!("Current: {}", self.angle_mode.to_string()))
                 }
             },
+            Special(ButtonEvent::WordSize) => {
+                let mode = self.env.integer_mode().cycle();
+                self.env.set_integer_mode(mode);
+                button.set_label(&mode.to_string());
+            }
+            Special(ButtonEvent::NumberMode) => {
+                use parser::BackendKind::*;
+                use parser::ComplexFormat::*;
+
+                let (backend, format, label) = match (self.env.backend(), self.complex_format) {
+                    (Float, _) => (Complex, Rectangular, "a+bi"),
+                    (Complex, Rectangular) => (Complex, Polar, "r∠θ"),
+                    (Complex, Polar) => (Rational, Rectangular, "ℚ"),
+                    (Rational, _) => (Decimal(parser::DEFAULT_PRECISION), Rectangular, "0.0…"),
+                    (Decimal(_), _) => (Float, Rectangular, "ℝ"),
+                };
+                self.env.set_backend(backend);
+                self.complex_format = format;
+                button.set_label(label);
+            }
             Special(ButtonEvent::Evaluate) => self.evaluate(),
+            Special(ButtonEvent::Simplify) => self.simplify(),
+            Special(ButtonEvent::Solve) => self.solve(),
             Special(ButtonEvent::Del) => self.backspace(1),
         }
     }
@@ -352,12 +450,23 @@ impl Calculator {
             CalcButton::new_simple("."),
             CalcButton::new_renamed("-", "(-)"),
             CalcButton::new("=", ButtonData::Special(ButtonEvent::Evaluate)),
+            // Sixth row
+            CalcButton::new_simple("%"),
+            CalcButton::new_renamed(" mod ", "mod"),
+            CalcButton::new_simple("//"),
+            CalcButton::new_renamed("!", "x!"),
+            CalcButton::new_function("gamma"),
+            CalcButton::new(
+                &parser::IntegerMode::default().to_string(),
+                ButtonData::Special(ButtonEvent::WordSize),
+            ),
+            CalcButton::new("ℝ", ButtonData::Special(ButtonEvent::NumberMode)),
         ]);
 
         window.set_title("Calculator");
         window.set_border_width(10);
         window.set_position(gtk::WindowPosition::Center);
-        window.set_default_size(555, 350);
+        window.set_default_size(555, 400);
 
         apply_css(&window, include_bytes!("../css/main.css"))
             .expect("ERROR: Could not load window screen")
@@ -383,6 +492,24 @@ impl Calculator {
 
         let del = CalcButton::new("DEL", ButtonData::Special(ButtonEvent::Del));
         let clear = CalcButton::new("AC", ButtonData::Special(ButtonEvent::Clear));
+        let simplify = CalcButton::new("SIMP", ButtonData::Special(ButtonEvent::Simplify));
+        let solve = CalcButton::new("SOLVE", ButtonData::Special(ButtonEvent::Solve));
+
+        grid.attach(
+            &solve.button,
+            ROW_LEN as i32 - 4,
+            textarea_height as i32 + 1,
+            1,
+            1,
+        );
+
+        grid.attach(
+            &simplify.button,
+            ROW_LEN as i32 - 3,
+            textarea_height as i32 + 1,
+            1,
+            1,
+        );
 
         grid.attach(
             &del.button,
@@ -404,7 +531,7 @@ impl Calculator {
             &state.err_label,
             1,
             textarea_height as i32 + 1,
-            ROW_LEN as i32 - 3,
+            ROW_LEN as i32 - 5,
             1,
         );
 
@@ -433,6 +560,8 @@ impl Calculator {
 
         state.buttons.push(del);
         state.buttons.push(clear);
+        state.buttons.push(simplify);
+        state.buttons.push(solve);
 
         let calc = Self {
             window,